@@ -0,0 +1,165 @@
+//! Inverse of the Base section of [`crate::compile`].
+
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+use thiserror::Error;
+use crate::{check_entry, open_output, read_input, CompileTarget, Entry, EntryList, EntryAttributes, MatchMethod};
+
+/// A line which could not be turned into an entry.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct UnrecognizedLine {
+    /// 1-based.
+    pub line_number: usize,
+    pub line: String,
+}
+
+#[derive(Debug)]
+pub struct Decompiled {
+    pub entries: EntryList,
+    pub unrecognized: Vec<UnrecognizedLine>,
+}
+
+#[derive(Error, Debug)]
+pub enum DecompileError {
+    #[error("JSON Serialize error: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Parses simple block rules in `text` back into entries.
+///
+/// Blank lines and comments are skipped. Other lines which can not be parsed, such as cosmetic rules and regex rules,
+/// are collected into [`Decompiled::unrecognized`].
+#[must_use]
+pub fn decompile(text: &str, target: CompileTarget) -> Decompiled {
+    let comment = target.comment_prefix();
+    let mut entries = vec![];
+    let mut unrecognized = vec![];
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(comment) || target.magic_line() == Some(line) {
+            continue
+        }
+
+        let (rule, note) = split_inline_comment(line, target);
+        match parse_line(rule, target, note).filter(|x| x.iter().all(|entry| check_entry(entry).is_ok())) {
+            Some(x) => entries.extend(x),
+            None => unrecognized.push(UnrecognizedLine {
+                line_number: i + 1,
+                line: line.to_string(),
+            }),
+        }
+    }
+
+    Decompiled {
+        entries: EntryList(entries),
+        unrecognized,
+    }
+}
+
+/// Decompiles `input` and writes the entries to `output` as JSON. `-` means stdin and stdout respectively.
+/// Returns the lines which could not be recognized.
+///
+/// # Errors
+/// Returns an error if reading or writing fails.
+pub fn decompile_file(
+    input: &Path,
+    output: &Path,
+    target: CompileTarget,
+) -> Result<Vec<UnrecognizedLine>, DecompileError> {
+    let text = read_input(input)?;
+    let Decompiled { entries, unrecognized } = decompile(&text, target);
+
+    let mut writer = open_output(output)?;
+    serde_json::to_writer_pretty(&mut writer, &entries)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+
+    Ok(unrecognized)
+}
+
+/// `||a.com^ # note` -> (`||a.com^`, `note`), if the target has inline comments. The comment must follow whitespace.
+fn split_inline_comment(line: &str, target: CompileTarget) -> (&str, Option<&str>) {
+    let comment = target.inline_comment_prefix().and_then(|prefix| line.split_once(prefix)).filter(|(rule, _)| rule.ends_with(char::is_whitespace));
+
+    comment.map_or((line, None), |(rule, note)| (rule.trim_end(), Some(note.trim()).filter(|x| !x.is_empty())))
+}
+
+/// A rule of hosts and dnsmasq may carry several values, as written with [`crate::CompileOptions::rule_limit_per_line`].
+fn parse_line(line: &str, target: CompileTarget, note: Option<&str>) -> Option<Vec<Entry>> {
+    match target {
+        CompileTarget::UBlockOrigin | CompileTarget::AdGuard | CompileTarget::AdblockPlus | CompileTarget::Brave => {
+            let (exception, rule) = line.strip_prefix("@@").map_or((false, line), |rule| (true, rule));
+            // uBlock Origin also accepts URL patterns as written for uBlacklist
+            let value = rule.strip_prefix("||").and_then(|x| x.strip_suffix('^')).or_else(|| url_pattern_value(rule))?;
+
+            Some(vec![entry_from_value(value, exception, note)])
+        }
+        CompileTarget::UBlackList => {
+            let (exception, rule) = line.strip_prefix('@').map_or((false, line), |rule| (true, rule));
+
+            Some(vec![entry_from_value(url_pattern_value(rule)?, exception, note)])
+        }
+        CompileTarget::Hosts => {
+            let (address, domains) = line.split_once(char::is_whitespace)?;
+
+            (address == "0.0.0.0" && !domains.contains(['/', '*'])).then(|| domains.split_whitespace().map(|domain| entry_from_value(domain, false, note)).collect())
+        }
+        CompileTarget::Dnsmasq | CompileTarget::SmartDns => {
+            let (prefix, suffix) = target.domain_rule_format()?;
+            let domains = line.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            // only dnsmasq joins several domains into a rule
+            let domains = target.line_grouping().map_or_else(|| vec![domains], |x| domains.split(x.separator).collect());
+
+            domains.iter().all(|domain| !domain.contains(['/', '*'])).then(|| domains.into_iter().map(|domain| entry_from_value(domain, false, note)).collect())
+        }
+    }
+}
+
+/// `*://{domain}/*` -> `{domain}`, `*://{path}` -> `{path}`
+fn url_pattern_value(rule: &str) -> Option<&str> {
+    let value = rule.strip_prefix("*://")?;
+
+    Some(value.strip_suffix("/*").filter(|domain| !domain.contains('/')).unwrap_or(value))
+}
+
+/// An IP address (IPv6 may be bracketed) is an IP, a value containing `/` is a path,
+/// and a value containing `*` is a wildcard.
+fn entry_from_value(value: &str, exception: bool, note: Option<&str>) -> Entry {
+    let attributes = EntryAttributes {
+        exception,
+        note: note.map(str::to_string),
+        ..EntryAttributes::default()
+    };
+    let ip = value.strip_prefix('[').and_then(|x| x.strip_suffix(']')).unwrap_or(value);
+    if ip.parse::<IpAddr>().is_ok() {
+        return Entry::Ip {
+            match_method: MatchMethod::Literal,
+            ip: ip.to_string(),
+            attributes,
+        }
+    }
+
+    let match_method = if value.contains('*') {
+        MatchMethod::Wildcard
+    } else {
+        MatchMethod::Literal
+    };
+
+    if value.contains('/') {
+        Entry::Path {
+            match_method,
+            path: value.to_string(),
+            attributes,
+        }
+    } else {
+        Entry::Domain {
+            match_method,
+            domain: value.to_string(),
+            attributes,
+        }
+    }
+}
@@ -0,0 +1,4146 @@
+//! Converts a JSON entry list into block lists for various blockers.
+//! The command line interface is a thin wrapper around [`compile`], [`syntax_check`], and [`decompile_file`].
+
+#![deny(clippy::all)]
+#![warn(clippy::pedantic, clippy::nursery)]
+
+mod decompile;
+
+pub use decompile::{decompile, decompile_file, DecompileError, Decompiled, UnrecognizedLine};
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env::VarError;
+use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::{Error as _, SeqAccess, Visitor};
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use flate2::write::GzEncoder;
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use time::OffsetDateTime;
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use strum::{EnumString, VariantNames};
+use thiserror::Error;
+
+/// Entries whose `match` is an array are expanded on deserialization. See [`MatchMethods`].
+#[derive(Serialize, Clone, Debug)]
+pub struct EntryList(pub Vec<Entry>);
+
+impl<'de> Deserialize<'de> for EntryList {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = Vec::<Entry<MatchMethods>>::deserialize(deserializer)?;
+
+        Ok(Self(entries.into_iter().enumerate().flat_map(|(i, x)| x.at(Position::Index(i + 1)).expand()).collect()))
+    }
+}
+
+// the schema describes the input, which may have an array of match methods
+impl JsonSchema for EntryList {
+    fn schema_name() -> Cow<'static, str> {
+        "EntryList".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        Vec::<Entry<MatchMethods>>::json_schema(generator)
+    }
+}
+
+/// TOML can not have an array at the top level, so entries are written as an array of tables:
+///
+/// ```toml
+/// [[entry]]
+/// type = "domain"
+/// match = "literal"
+/// domain = "example.com"
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlEntryList {
+    #[serde(default)]
+    entry: Vec<Entry<MatchMethods>>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Eq, PartialEq, Hash, Debug)]
+/// Unknown fields are rejected, so that a misspelled field is not silently ignored.
+#[serde(tag = "type", deny_unknown_fields)]
+#[schemars(rename = "Entry")]
+// `M` is `MatchMethods` only while an input is deserialized
+pub enum Entry<M = MatchMethod> {
+    #[serde(rename = "domain")]
+    Domain {
+        #[serde(rename = "match")]
+        match_method: M,
+        domain: String,
+        #[serde(flatten)]
+        attributes: EntryAttributes,
+    },
+    #[serde(rename = "path")]
+    Path {
+        #[serde(rename = "match")]
+        match_method: M,
+        path: String,
+        #[serde(flatten)]
+        attributes: EntryAttributes,
+    },
+    /// IPv4 or IPv6 address. Only `literal` and `regex` are supported.
+    #[serde(rename = "ip")]
+    Ip {
+        #[serde(rename = "match")]
+        match_method: M,
+        ip: String,
+        #[serde(flatten)]
+        attributes: EntryAttributes,
+    },
+    /// IPv4 or IPv6 address range, such as `192.0.2.0/24`. Host bits must be zero.
+    /// Only octet-aligned IPv4 ranges are expressed natively; others require [`CompileOptions::expand_cidr`].
+    #[serde(rename = "cidr")]
+    Cidr {
+        cidr: String,
+        #[serde(flatten)]
+        attributes: EntryAttributes,
+    },
+    /// Regular expression passed through as a rule, matched against the whole URL. Not a host or path shape,
+    /// so only targets with regex rules emit it.
+    #[serde(rename = "regex")]
+    Regex {
+        pattern: String,
+        #[serde(skip)]
+        #[schemars(skip)]
+        provenance: Provenance,
+    },
+    /// Emitted as a comment line in the Base section. Does not match anything.
+    #[serde(rename = "comment")]
+    Comment {
+        text: String,
+    },
+}
+
+/// Attributes shared by domain, path, IP, and CIDR entries.
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Default, Eq, PartialEq, Hash, Debug)]
+// merged into each variant of the schema, which has its own description
+#[schemars(description = "")]
+pub struct EntryAttributes {
+    /// Emits an exception (allow) rule instead of a block rule.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub exception: bool,
+    /// `false` skips this entry unless disabled entries are included. Defaults to `true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// Human readable note, emitted as a comment next to the rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Feature flags, one of which must be given for this entry to be emitted. Always emitted if omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub only_for: Option<Vec<String>>,
+    /// Date when this entry was added, in `YYYY-MM-DD`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added: Option<String>,
+    /// First-party domains on which this entry applies (`$domain=`). Applies everywhere if omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<Vec<String>>,
+    /// uBlock Origin options appended to the rule, such as `third-party`. Ignored by other targets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modifiers: Option<Vec<String>>,
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub provenance: Provenance,
+}
+
+impl<M> Entry<M> {
+    /// `None` for comments.
+    const fn provenance_mut(&mut self) -> Option<&mut Provenance> {
+        match self {
+            Self::Domain { attributes, .. } | Self::Path { attributes, .. } | Self::Ip { attributes, .. } | Self::Cidr { attributes, .. } => Some(&mut attributes.provenance),
+            Self::Regex { provenance, .. } => Some(provenance),
+            Self::Comment { .. } => None,
+        }
+    }
+
+    /// Sets [`Provenance::position`], keeping the path.
+    const fn at(mut self, position: Position) -> Self {
+        if let Some(provenance) = self.provenance_mut() {
+            provenance.position = Some(position);
+        }
+
+        self
+    }
+}
+
+impl Entry<MatchMethods> {
+    /// One entry for each match method, in the given order.
+    fn expand(self) -> Vec<Entry> {
+        match self {
+            Self::Domain { match_method, domain, attributes } => {
+                match_method.0.into_iter().map(|match_method| Entry::Domain { match_method, domain: domain.clone(), attributes: attributes.clone() }).collect()
+            }
+            Self::Path { match_method, path, attributes } => {
+                match_method.0.into_iter().map(|match_method| Entry::Path { match_method, path: path.clone(), attributes: attributes.clone() }).collect()
+            }
+            Self::Ip { match_method, ip, attributes } => {
+                match_method.0.into_iter().map(|match_method| Entry::Ip { match_method, ip: ip.clone(), attributes: attributes.clone() }).collect()
+            }
+            Self::Cidr { cidr, attributes } => vec![Entry::Cidr { cidr, attributes }],
+            Self::Regex { pattern, provenance } => vec![Entry::Regex { pattern, provenance }],
+            Self::Comment { text } => vec![Entry::Comment { text }],
+        }
+    }
+}
+
+/// Where an entry was loaded from, set while loading. Shown by [`CompileOptions::emit_metadata_comment`].
+/// Every provenance equals each other, so that it does not affect deduplication.
+#[derive(Clone, Default, Debug)]
+pub struct Provenance {
+    /// Input file. `-` is stdin. `None` if the entry was not loaded from a file.
+    pub path: Option<Arc<Path>>,
+    pub position: Option<Position>,
+}
+
+impl Provenance {
+    #[must_use]
+    pub const fn is_known(&self) -> bool {
+        self.path.is_some() || self.position.is_some()
+    }
+}
+
+impl PartialEq for Provenance {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Provenance {}
+
+impl Hash for Provenance {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+/// `list.json entry 3`, or `stdin line 3`.
+impl Display for Provenance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.path {
+            Some(path) if path.as_os_str() == "-" => f.write_str("stdin")?,
+            Some(path) => write!(f, "{}", path.display())?,
+            None => {}
+        }
+
+        match self.position {
+            Some(position) if self.path.is_some() => write!(f, " {position}"),
+            Some(position) => write!(f, "{position}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Position of an entry in its input, 1-based.
+#[derive(Copy, Clone, Debug)]
+pub enum Position {
+    /// Index in the entries of a JSON, YAML, or TOML input. An entry with several match methods shares the index.
+    Index(usize),
+    /// Line of an NDJSON or lines input.
+    Line(usize),
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Index(x) => write!(f, "entry {x}"),
+            Self::Line(x) => write!(f, "line {x}"),
+        }
+    }
+}
+
+/// `match` of an input entry: a [`MatchMethod`], or a non-empty array of them, such as `["literal", "prefix"]`.
+/// An entry with an array is expanded into one entry for each method.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct MatchMethods(pub Vec<MatchMethod>);
+
+impl<'de> Deserialize<'de> for MatchMethods {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MatchMethodsVisitor;
+
+        impl<'de> Visitor<'de> for MatchMethodsVisitor {
+            type Value = MatchMethods;
+
+            fn expecting(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                f.write_str("a match method or an array of match methods")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                // the same error as a single match method
+                v.parse().map(|x| MatchMethods(vec![x])).map_err(E::custom)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut methods = vec![];
+                while let Some(x) = seq.next_element()? {
+                    methods.push(x);
+                }
+
+                if methods.is_empty() {
+                    return Err(A::Error::custom("match must have at least one method"))
+                }
+
+                Ok(MatchMethods(methods))
+            }
+        }
+
+        deserializer.deserialize_any(MatchMethodsVisitor)
+    }
+}
+
+impl JsonSchema for MatchMethods {
+    fn schema_name() -> Cow<'static, str> {
+        "MatchMethods".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        let method = generator.subschema_for::<MatchMethod>();
+        json_schema!({
+            "anyOf": [
+                method,
+                {
+                    "type": "array",
+                    "items": method,
+                    "minItems": 1,
+                },
+            ],
+        })
+    }
+}
+
+impl Entry {
+    #[must_use]
+    pub const fn entry_type(&self) -> EntryType {
+        match self {
+            Self::Domain { .. } => EntryType::Domain,
+            Self::Path { .. } => EntryType::Path,
+            Self::Ip { .. } => EntryType::Ip,
+            Self::Cidr { .. } => EntryType::Cidr,
+            Self::Regex { .. } => EntryType::Regex,
+            Self::Comment { .. } => EntryType::Comment,
+        }
+    }
+
+    /// `None` for CIDR, regex, and comments.
+    #[must_use]
+    pub const fn match_method(&self) -> Option<MatchMethod> {
+        match self {
+            Self::Domain { match_method, .. } | Self::Path { match_method, .. } | Self::Ip { match_method, .. } => Some(*match_method),
+            Self::Cidr { .. } | Self::Regex { .. } | Self::Comment { .. } => None,
+        }
+    }
+
+    /// `None` for regex and comments.
+    #[must_use]
+    pub const fn attributes(&self) -> Option<&EntryAttributes> {
+        match self {
+            Self::Domain { attributes, .. } | Self::Path { attributes, .. } | Self::Ip { attributes, .. } | Self::Cidr { attributes, .. } => Some(attributes),
+            Self::Regex { .. } | Self::Comment { .. } => None,
+        }
+    }
+
+    #[must_use]
+    pub fn is_exception(&self) -> bool {
+        self.attributes().is_some_and(|x| x.exception)
+    }
+
+    /// `false` if `enabled` is explicitly `false`. Regex and comments are always enabled.
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.attributes().is_none_or(|x| x.enabled != Some(false))
+    }
+
+    #[must_use]
+    pub fn note(&self) -> Option<&str> {
+        self.attributes().and_then(|x| x.note.as_deref())
+    }
+
+    /// `None` for regex, comments, and entries emitted for every feature flag.
+    #[must_use]
+    pub fn only_for(&self) -> Option<&[String]> {
+        self.attributes().and_then(|x| x.only_for.as_deref())
+    }
+
+    /// `None` for regex, comments, and entries without a date.
+    #[must_use]
+    pub fn added(&self) -> Option<&str> {
+        self.attributes().and_then(|x| x.added.as_deref())
+    }
+
+    /// `None` for regex, comments, and entries without a scope.
+    #[must_use]
+    pub fn scope(&self) -> Option<&[String]> {
+        self.attributes().and_then(|x| x.scope.as_deref())
+    }
+
+    /// `None` for regex, comments, and entries without modifiers.
+    #[must_use]
+    pub fn modifiers(&self) -> Option<&[String]> {
+        self.attributes().and_then(|x| x.modifiers.as_deref())
+    }
+
+    /// Where this entry was loaded from. `None` for comments.
+    #[must_use]
+    pub const fn provenance(&self) -> Option<&Provenance> {
+        match self {
+            Self::Domain { attributes, .. } | Self::Path { attributes, .. } | Self::Ip { attributes, .. } | Self::Cidr { attributes, .. } => Some(&attributes.provenance),
+            Self::Regex { provenance, .. } => Some(provenance),
+            Self::Comment { .. } => None,
+        }
+    }
+
+    /// `true` if [`Entry::only_for`] is omitted, or names one of `feature_flags`.
+    #[must_use]
+    pub fn is_active_for(&self, feature_flags: &[GenerateTargetPlatform]) -> bool {
+        self.only_for().is_none_or(|only_for| {
+            only_for.iter().any(|x| x.parse().is_ok_and(|x| feature_flags.contains(&x)))
+        })
+    }
+
+    /// Domain, path, IP address, CIDR, regex pattern, or comment text, depending on the type.
+    #[must_use]
+    pub fn value(&self) -> &str {
+        match self {
+            Self::Domain { domain, .. } => domain,
+            Self::Path { path, .. } => path,
+            Self::Ip { ip, .. } => ip,
+            Self::Cidr { cidr, .. } => cidr,
+            Self::Regex { pattern, .. } => pattern,
+            Self::Comment { text } => text,
+        }
+    }
+}
+
+/// Deserialized from `K=V`.
+#[derive(Clone, Eq, PartialEq, Debug, DeserializeFromStr)]
+pub struct HeaderAttribute {
+    pub key: String,
+    pub value: String,
+}
+
+/// Calendar date in `YYYY-MM-DD`.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub struct IsoDate {
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+impl Display for IsoDate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("expected YYYY-MM-DD, got '{input}': {reason}")]
+pub struct IsoDateParseError {
+    pub input: String,
+    pub reason: &'static str,
+}
+
+impl FromStr for IsoDate {
+    type Err = IsoDateParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason| IsoDateParseError { input: s.to_string(), reason };
+        let mut parts = s.split('-');
+        let (Some(year), Some(month), Some(day), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid("must consist of year, month, and day"))
+        };
+
+        if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+            return Err(invalid("must be zero-padded"))
+        }
+
+        let (Ok(year), Ok(month), Ok(day)) = (year.parse::<u16>(), month.parse::<u8>(), day.parse::<u8>()) else {
+            return Err(invalid("must consist of digits"))
+        };
+
+        let calendar_month = time::Month::try_from(month).map_err(|_| invalid("month is out of range"))?;
+        time::Date::from_calendar_date(i32::from(year), calendar_month, day).map_err(|_| invalid("day is out of range"))?;
+
+        Ok(Self { year, month, day })
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("expected K=V, got '{input}'")]
+pub struct HeaderAttributeParseError {
+    pub input: String,
+}
+
+impl FromStr for HeaderAttribute {
+    type Err = HeaderAttributeParseError;
+
+    /// Splits `s` at the first `=`. The value may be empty or contain `=`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s.split_once('=').ok_or_else(|| HeaderAttributeParseError { input: s.to_string() })?;
+        Ok(Self {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum AdGuardModifier {
+    /// `$important`
+    Important,
+    /// `$dnsrewrite=V`
+    DnsRewrite(String),
+}
+
+impl FromStr for AdGuardModifier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            None if s == "important" => Ok(Self::Important),
+            Some(("dnsrewrite", value)) => Ok(Self::DnsRewrite(value.to_string())),
+            _ => Err(format!("expected 'important' or 'dnsrewrite=V', got '{s}'")),
+        }
+    }
+}
+
+impl AdGuardModifier {
+    fn serialize(&self) -> String {
+        match self {
+            Self::Important => "important".to_string(),
+            Self::DnsRewrite(value) => format!("dnsrewrite={value}"),
+        }
+    }
+}
+
+/// Parsed case-insensitively, such as `ublockorigin`.
+#[derive(EnumString, strum::Display, VariantNames, Copy, Clone, Eq, PartialEq, Debug, DeserializeFromStr, SerializeDisplay)]
+#[strum(ascii_case_insensitive)]
+pub enum CompileTarget {
+    #[strum(serialize = "uBlackList")]
+    UBlackList,
+    #[strum(serialize = "uBlockOrigin")]
+    UBlockOrigin,
+    /// hosts file (`/etc/hosts`, Pi-hole, etc.). Only literal domain entries can be expressed.
+    #[strum(serialize = "hosts")]
+    Hosts,
+    /// `AdGuard` DNS filter. Only domain entries can be expressed.
+    #[strum(serialize = "AdGuard")]
+    AdGuard,
+    /// dnsmasq configuration. Only literal domain entries can be expressed.
+    #[strum(serialize = "dnsmasq")]
+    Dnsmasq,
+    /// Adblock Plus filter list. Search engine rules are not supported.
+    #[strum(serialize = "AdblockPlus")]
+    AdblockPlus,
+    /// Brave filter list, in the Adblock Plus syntax. Search engine rules are not supported.
+    #[strum(serialize = "Brave")]
+    Brave,
+    /// `SmartDNS` configuration. Only literal domain entries can be expressed.
+    #[strum(serialize = "SmartDNS")]
+    SmartDns,
+}
+
+impl CompileTarget {
+    /// Prefix of comment lines, including header attributes.
+    #[must_use]
+    pub const fn comment_prefix(self) -> &'static str {
+        match self {
+            Self::UBlackList | Self::Hosts | Self::Dnsmasq | Self::SmartDns => "#",
+            Self::UBlockOrigin | Self::AdGuard | Self::AdblockPlus | Self::Brave => "!",
+        }
+    }
+
+    /// Prefix of comments placed after a rule on the same line, if the target can parse them.
+    /// Otherwise, notes are emitted as a preceding comment line.
+    #[must_use]
+    pub const fn inline_comment_prefix(self) -> Option<&'static str> {
+        match self {
+            // uBlock Origin ignores ` #` and after in network filters, like hosts files
+            Self::Hosts | Self::UBlockOrigin => Some("#"),
+            Self::UBlackList | Self::AdGuard | Self::Dnsmasq | Self::AdblockPlus | Self::Brave | Self::SmartDns => None,
+        }
+    }
+
+    /// File name used when writing every target into a directory.
+    #[must_use]
+    pub fn file_name(self, compression: Option<OutputCompression>) -> String {
+        let stem = match self {
+            Self::UBlackList => "ublacklist",
+            Self::UBlockOrigin => "ublockorigin",
+            Self::Hosts => "hosts",
+            Self::AdGuard => "adguard",
+            Self::Dnsmasq => "dnsmasq",
+            Self::AdblockPlus => "adblockplus",
+            Self::Brave => "brave",
+            Self::SmartDns => "smartdns",
+        };
+
+        match compression {
+            None => format!("{stem}.txt"),
+            Some(OutputCompression::Gzip) => format!("{stem}.txt.gz"),
+        }
+    }
+
+    /// `true` if `*.example.com` is emitted as a rule which also matches `example.com` and every subdomain.
+    const fn wildcard_matches_subdomains(self) -> bool {
+        !matches!(self, Self::Hosts)
+    }
+
+    /// `true` if network rules accept `$domain=`.
+    const fn supports_domain_option(self) -> bool {
+        matches!(self, Self::UBlockOrigin | Self::AdblockPlus | Self::Brave)
+    }
+
+    /// Prefix and suffix around the domain of a Base rule, if the target is a DNS server which can express only literal
+    /// domains. Subdomains are always matched.
+    pub(crate) const fn domain_rule_format(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Self::Dnsmasq => Some(("address=/", "/0.0.0.0")),
+            // `#` answers with SOA
+            Self::SmartDns => Some(("domain-rules /", "/ -address #")),
+            Self::UBlackList | Self::UBlockOrigin | Self::Hosts | Self::AdGuard | Self::AdblockPlus | Self::Brave => None,
+        }
+    }
+
+    /// Format of a Base rule carrying several values, if the target accepts it.
+    /// See [`CompileOptions::rule_limit_per_line`].
+    const fn line_grouping(self) -> Option<LineGrouping> {
+        match self {
+            // 0.0.0.0 a.example b.example
+            Self::Hosts => Some(LineGrouping { prefix: "0.0.0.0 ", separator: " ", suffix: "" }),
+            // address=/a.example/b.example/0.0.0.0
+            Self::Dnsmasq => Some(LineGrouping { prefix: "address=/", separator: "/", suffix: "/0.0.0.0" }),
+            Self::UBlackList | Self::UBlockOrigin | Self::AdGuard | Self::AdblockPlus | Self::Brave | Self::SmartDns => None,
+        }
+    }
+
+    /// First line of the output which identifies the format, if any.
+    #[must_use]
+    pub const fn magic_line(self) -> Option<&'static str> {
+        match self {
+            Self::AdblockPlus | Self::Brave => Some("[Adblock Plus 2.0]"),
+            Self::UBlackList | Self::UBlockOrigin | Self::Hosts | Self::AdGuard | Self::Dnsmasq | Self::SmartDns => None,
+        }
+    }
+}
+
+/// Parsed case-insensitively, such as `googlesearchprefix`.
+#[derive(EnumString, strum::Display, VariantNames, Copy, Clone, Eq, PartialEq, Debug, DeserializeFromStr, SerializeDisplay)]
+#[strum(ascii_case_insensitive)]
+pub enum GenerateTargetPlatform {
+    Base,
+    /// Generates Google search block rule. Match if and only if the URL prefix matches in deny list entry.
+    GoogleSearchPrefix,
+    /// Also generates Google search block rule. Match if and only if the URL contains deny list entry.
+    GoogleSearchFuzzy,
+    /// Generates DuckDuckGo search block rule. Match if and only if the URL prefix matches in deny list entry.
+    DuckDuckGoPrefix,
+    /// Also generates DuckDuckGo search block rule. Match if and only if the URL contains deny list entry.
+    DuckDuckGoFuzzy,
+    /// Generates Bing search block rule. Match if and only if the URL prefix matches in deny list entry.
+    BingPrefix,
+    /// Also generates Bing search block rule. Match if and only if the URL contains deny list entry.
+    BingFuzzy,
+    /// Generates Yandex search block rule. Match if and only if the URL prefix matches in deny list entry.
+    YandexPrefix,
+    /// Also generates Yandex search block rule. Match if and only if the URL contains deny list entry.
+    YandexFuzzy,
+}
+
+#[derive(EnumString, strum::Display, VariantNames, Copy, Clone, Eq, PartialEq, Hash, Debug, DeserializeFromStr, SerializeDisplay)]
+pub enum MatchMethod {
+    #[strum(serialize = "literal")]
+    Literal,
+    /// The value is a regular expression. For domain entries, it is matched against the host name.
+    #[strum(serialize = "regex")]
+    Regex,
+    /// The value may contain `*`, which matches any sequence of characters.
+    /// In the host part, `*` is only allowed as the leading label (e.g. `*.example.com`).
+    #[strum(serialize = "wildcard")]
+    Wildcard,
+    /// The value is matched anywhere in the URL, without anchors.
+    /// Short values may match unexpectedly, so [`syntax_check`] warns about them.
+    #[strum(serialize = "substring")]
+    Substring,
+    /// The value is matched at the end, without a label boundary: `example.com` also matches `badexample.com`,
+    /// unlike `||example.com^`. For domain entries, it is matched against the host name, and for path entries,
+    /// against the end of the URL.
+    #[strum(serialize = "suffix")]
+    Suffix,
+    /// Only for path entries. The value is matched at the start of the URL, such as `example.com/ads/`.
+    /// A value starting with `/` is matched at the start of the path on every host, such as `/ads/`.
+    #[strum(serialize = "prefix")]
+    Prefix,
+}
+
+/// `type` of an [`Entry`].
+#[derive(EnumString, strum::Display, VariantNames, Copy, Clone, Eq, PartialEq, Hash, Debug, SerializeDisplay)]
+pub enum EntryType {
+    #[strum(serialize = "domain")]
+    Domain,
+    #[strum(serialize = "path")]
+    Path,
+    #[strum(serialize = "ip")]
+    Ip,
+    #[strum(serialize = "cidr")]
+    Cidr,
+    #[strum(serialize = "regex")]
+    Regex,
+    #[strum(serialize = "comment")]
+    Comment,
+}
+
+#[derive(EnumString, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum InputFormat {
+    #[strum(serialize = "json")]
+    Json,
+    /// JSON with `// line` and `/* block */` comments.
+    #[strum(serialize = "jsonc")]
+    Jsonc,
+    #[strum(serialize = "yaml")]
+    Yaml,
+    /// Entries are written as an array of tables named `entry`.
+    #[strum(serialize = "toml")]
+    Toml,
+    /// One literal domain per line. Blank lines and lines starting with `#` or `!` are skipped.
+    #[strum(serialize = "lines")]
+    Lines,
+    /// One JSON entry per line, read without holding the whole input in memory. Blank lines are skipped.
+    #[strum(serialize = "ndjson")]
+    Ndjson,
+}
+
+impl InputFormat {
+    /// `.jsonc` is JSON with comments, `.yaml` and `.yml` are YAML, `.toml` is TOML, `.txt` is lines,
+    /// `.ndjson` and `.jsonl` are NDJSON, and everything else (including stdin) is JSON.
+    #[must_use]
+    pub fn detect(path: &Path) -> Self {
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("jsonc") => Self::Jsonc,
+            Some("yaml" | "yml") => Self::Yaml,
+            Some("toml") => Self::Toml,
+            Some("txt") => Self::Lines,
+            Some("ndjson" | "jsonl") => Self::Ndjson,
+            _ => Self::Json,
+        }
+    }
+}
+
+// (de)serialized through strum, so the derive macro can not see the names
+impl JsonSchema for MatchMethod {
+    fn schema_name() -> Cow<'static, str> {
+        "MatchMethod".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "string",
+            "enum": Self::VARIANTS,
+        })
+    }
+}
+
+/// JSON Schema of [`EntryList`], which editors can use to validate input files.
+///
+/// # Panics
+/// Never. The generated schema is always serializable.
+#[must_use]
+pub fn entry_list_schema() -> String {
+    serde_json::to_string_pretty(&schemars::schema_for!(EntryList)).expect("schema must be serializable")
+}
+
+#[derive(Error, Debug)]
+pub enum CompileError {
+    #[error("JSON Deserialize error: {0}")]
+    Deserialize(#[from] serde_json::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Unsupported feature combination")]
+    UnsupportedFeatureSet,
+    #[error("Both {first:?} and {second:?} must not be used at the same time; please separate the call")]
+    ConflictingFeatures {
+        first: GenerateTargetPlatform,
+        second: GenerateTargetPlatform,
+    },
+    #[error("Nothing to generate: no feature flag was given, or every entry was filtered out")]
+    NothingToGenerate,
+    #[error("{count} warnings were emitted, and warnings are treated as errors")]
+    WarningsAsErrors {
+        count: usize,
+    },
+    #[error("Failed to start worker threads: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    #[error("Too many entries: {count} entries were loaded, but the limit is {limit}")]
+    TooManyEntries {
+        count: usize,
+        limit: usize,
+    },
+    #[error("Invalid internationalized domain '{domain}': {source}")]
+    InvalidIdn {
+        domain: String,
+        #[source]
+        source: idna::Errors,
+    },
+    #[error("CIDR '{cidr}' has more than {limit} addresses to expand")]
+    CidrTooLarge {
+        cidr: String,
+        limit: u128,
+    },
+    #[error("Failed to read {}: {source}", .path.display())]
+    AuxiliaryFile {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Invalid header attribute at {}:{line_number}: {source}", .path.display())]
+    InvalidHeaderFile {
+        path: PathBuf,
+        line_number: usize,
+        #[source]
+        source: HeaderAttributeParseError,
+    },
+    #[error("Duplicated header attribute key '{key}'")]
+    DuplicateHeader {
+        key: String,
+    },
+    #[error("Invalid template '{template}': {reason}")]
+    InvalidTemplate {
+        template: String,
+        reason: &'static str,
+    },
+    #[error("Failed to expand header '{key}': {reason}")]
+    HeaderExpansion {
+        key: String,
+        reason: String,
+    },
+    #[error("Syntax error: {0}")]
+    Syntax(#[from] SyntaxCheckError),
+    #[error("Failed to load {}: {source}", .path.display())]
+    Input {
+        path: PathBuf,
+        #[source]
+        source: SyntaxCheckError,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum SyntaxCheckError {
+    #[error("JSON Deserialize error: {source} (near '{snippet}')")]
+    Deserialize {
+        #[source]
+        source: serde_json::Error,
+        /// A few characters of the input around the error position.
+        snippet: String,
+    },
+    #[error("JSON Deserialize error at line {line_number}: {source}")]
+    DeserializeLine {
+        /// 1-based.
+        line_number: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("YAML Deserialize error: {0}")]
+    Yaml(#[from] serde_norway::Error),
+    #[error("TOML Deserialize error: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid regex '{pattern}': {source}")]
+    InvalidRegex {
+        pattern: String,
+        #[source]
+        source: regex::Error,
+    },
+    #[error("Invalid wildcard '{pattern}': {reason}")]
+    InvalidWildcard {
+        pattern: String,
+        reason: &'static str,
+    },
+    #[error("Invalid domain '{value}': {reason}")]
+    InvalidDomain {
+        value: String,
+        reason: &'static str,
+    },
+    #[error("Invalid path '{value}': {reason}")]
+    InvalidPath {
+        value: String,
+        reason: &'static str,
+    },
+    #[error("Invalid path '{value}': must not contain a scheme such as 'https://'; use '{suggestion}' instead")]
+    PathWithScheme {
+        value: String,
+        suggestion: String,
+    },
+    #[error("Invalid {entry_type} '{value}': '{character}' is special in filter syntax; use 'regex', or percent-encode it as '{encoded}'")]
+    AnchorCharacter {
+        entry_type: EntryType,
+        value: String,
+        character: char,
+        encoded: &'static str,
+    },
+    #[error("Empty {entry_type} after trimming whitespace")]
+    EmptyValue {
+        entry_type: EntryType,
+    },
+    /// `//` is not a regex rule for any target.
+    #[error("Empty regex pattern")]
+    EmptyPattern,
+    #[error("Invalid note {note:?}: must not contain a line break")]
+    InvalidNote {
+        note: String,
+    },
+    #[error("Invalid comment {text:?}: must not contain a line break")]
+    InvalidComment {
+        text: String,
+    },
+    #[error("Invalid date '{value}' in added: {reason}")]
+    InvalidDate {
+        value: String,
+        reason: &'static str,
+    },
+    #[error("Invalid modifier '{modifier}': {reason}")]
+    InvalidModifier {
+        modifier: String,
+        reason: &'static str,
+    },
+    #[error("Invalid feature flag '{flag}' in only_for")]
+    InvalidFeatureFlag {
+        flag: String,
+    },
+    #[error("Invalid IP address '{value}': {reason}")]
+    InvalidIp {
+        value: String,
+        reason: &'static str,
+    },
+    #[error("Invalid CIDR '{value}': {reason}")]
+    InvalidCidr {
+        value: String,
+        reason: &'static str,
+    },
+    #[error("Invalid substring '{value}': {reason}")]
+    InvalidSubstring {
+        value: String,
+        reason: &'static str,
+    },
+    #[error("Invalid suffix '{value}': {reason}")]
+    InvalidSuffix {
+        value: String,
+        reason: &'static str,
+    },
+    #[error("Invalid prefix '{value}': {reason}")]
+    InvalidPrefix {
+        value: String,
+        reason: &'static str,
+    },
+    #[error("Both blocked and allowed by exception entries: {}", .conflicts.join(", "))]
+    ConflictingEntries {
+        /// Such as `domain 'example.com'`.
+        conflicts: Vec<String>,
+    },
+    /// An invalid entry loaded from an input, with its [`Provenance::position`].
+    #[error("{source}")]
+    Positioned {
+        position: Position,
+        #[source]
+        source: Box<Self>,
+    },
+}
+
+#[derive(Clone, Debug)]
+#[allow(clippy::struct_excessive_bools)]
+// each flag corresponds to an independent command line switch
+pub struct CompileOptions {
+    /// Format of every input. Detected from each file extension if `None`.
+    pub input_format: Option<InputFormat>,
+    /// Trims leading and trailing whitespace of domain, path, IP, and CIDR values before they are validated.
+    /// A value which becomes empty is rejected with [`SyntaxCheckError::EmptyValue`].
+    pub trim_whitespace: bool,
+    pub target: CompileTarget,
+    pub feature_flags: Vec<GenerateTargetPlatform>,
+    /// Emitted as comments at the top of the output.
+    pub header_attributes: Vec<HeaderAttribute>,
+    /// File of header attributes, one `K=V` per line, emitted before [`CompileOptions::header_attributes`].
+    /// Blank lines and lines starting with `#` are skipped.
+    pub header_file: Option<PathBuf>,
+    /// Automatically computed header attributes, emitted after [`CompileOptions::header_attributes`].
+    pub auto_header: Vec<AutoHeaderField>,
+    /// Written verbatim after the magic line, before header attributes. Counted as header lines.
+    pub prepend_file: Option<PathBuf>,
+    /// Written verbatim after every generated section.
+    pub append_file: Option<PathBuf>,
+    /// How to handle header attributes sharing the same key.
+    pub header_dup_policy: HeaderDupPolicy,
+    /// Replaces `${VAR}` in values of [`CompileOptions::header_attributes`] and [`CompileOptions::header_file`]
+    /// with the environment variable `VAR`. Fails with [`CompileError::HeaderExpansion`] if it is not defined.
+    pub expand_env: bool,
+    /// Only valid with [`CompileTarget::AdGuard`].
+    pub adguard_modifiers: Vec<AdGuardModifier>,
+    /// Fails with [`CompileError::TooManyEntries`] if more entries than this are loaded, including comments.
+    pub max_entries: Option<usize>,
+    /// Keeps entries whose `enabled` is `false`. They are skipped by default.
+    pub include_disabled: bool,
+    /// Keeps only entries whose `added` is on or after this date. Entries without a date, including comments, are skipped.
+    pub modified_after: Option<IsoDate>,
+    /// Keeps only entries of these types, before [`CompileOptions::expand_cidr`]. Every type is kept if empty.
+    pub only_types: Vec<EntryType>,
+    /// Expands CIDR entries into IP entries, so that every target can express them.
+    /// Fails with [`CompileError::CidrTooLarge`] for a range larger than [`MAX_CIDR_EXPANSION`] addresses.
+    pub expand_cidr: bool,
+    /// Lowercases domains and the host of paths, before [`CompileOptions::dedup`] and [`CompileOptions::sort`].
+    /// Regex entries are kept as is.
+    pub lowercase: bool,
+    /// Strips a single trailing dot from literal and wildcard domain entries (`example.com.` -> `example.com`),
+    /// before [`CompileOptions::dedup`]. The trailing dot is passed through as is otherwise.
+    pub normalize_trailing_dot: bool,
+    /// Converts internationalized domains and the host of paths into punycode (`xn--`). Regex and substring entries are kept as is.
+    pub punycode: bool,
+    /// Removes a leading `www.` from literal and wildcard domain entries, before [`CompileOptions::dedup`].
+    pub strip_www: bool,
+    /// Removes duplicated entries. The first occurrence is kept.
+    pub dedup: Option<DedupMode>,
+    /// Sorts entries case-insensitively by [`Entry::value`]. Comments are sorted by their text as well.
+    pub sort: bool,
+    /// Groups the Base section by entry type, under a comment banner per type. With [`CompileOptions::sort`],
+    /// entries are sorted within each group. Comment entries are skipped.
+    pub group_by_type: bool,
+    /// Joins up to this many rules of the Base section into one line, if the target accepts several values per line
+    /// (hosts and dnsmasq). Ignored with a warning for the other targets and with [`CompileOptions::template`].
+    /// A comment or a note breaks the line.
+    pub rule_limit_per_line: Option<NonZeroUsize>,
+    /// Emits a comment line naming the input file and the position of the entry before each rule of the Base section,
+    /// such as `! source: list.json entry 3`. See [`Entry::provenance`].
+    pub emit_metadata_comment: bool,
+    /// Fails with [`CompileError::NothingToGenerate`] instead of succeeding with no rules.
+    pub fail_on_empty: bool,
+    /// Fails with [`CompileError::WarningsAsErrors`] after writing the output if any warning was emitted,
+    /// including ones printed only with [`LogLevel::Verbose`].
+    pub fail_on_warning: bool,
+    /// Compresses the output written by [`compile`].
+    pub compression: Option<OutputCompression>,
+    /// Overrides [`CompileTarget::comment_prefix`] for header attributes, comment entries, and notes.
+    /// A space is inserted after the prefix.
+    pub comment_prefix: Option<String>,
+    /// Replaces the rule of every entry in the Base section. `{value}` is substituted with [`Entry::value`].
+    /// Exception entries are skipped.
+    pub template: Option<String>,
+    /// Line break of every output line, including prepended and appended files.
+    pub line_ending: LineEnding,
+    pub output_format: OutputFormat,
+    /// Scheme matched by [`CompileTarget::UBlackList`] rules. Other targets match any scheme.
+    pub scheme: Scheme,
+    /// Also emits `*://*.example.com/*` for each literal domain entry of [`CompileTarget::UBlackList`], which otherwise
+    /// matches only the exact host. Other targets already match subdomains, except for [`CompileTarget::Hosts`].
+    pub include_subdomains: bool,
+    /// Number of threads used to serialize rules. `1` serializes on the current thread, and `0` uses every CPU.
+    /// The output is the same regardless of this value.
+    pub jobs: usize,
+    /// Shows a progress bar of serialized entries on stderr. Hidden if stderr is not a terminal,
+    /// or with [`LogLevel::Quiet`].
+    pub progress: bool,
+    /// How much is printed to stderr.
+    pub log_level: LogLevel,
+}
+
+impl CompileOptions {
+    /// Options which generate `feature_flags` for `target` from entries as is.
+    #[must_use]
+    pub const fn new(target: CompileTarget, feature_flags: Vec<GenerateTargetPlatform>) -> Self {
+        Self {
+            input_format: None,
+            trim_whitespace: false,
+            target,
+            feature_flags,
+            header_attributes: vec![],
+            header_file: None,
+            auto_header: vec![],
+            prepend_file: None,
+            append_file: None,
+            header_dup_policy: HeaderDupPolicy::KeepAll,
+            expand_env: false,
+            adguard_modifiers: vec![],
+            max_entries: None,
+            include_disabled: false,
+            modified_after: None,
+            only_types: vec![],
+            expand_cidr: false,
+            lowercase: false,
+            normalize_trailing_dot: false,
+            punycode: false,
+            strip_www: false,
+            dedup: None,
+            sort: false,
+            group_by_type: false,
+            rule_limit_per_line: None,
+            emit_metadata_comment: false,
+            fail_on_empty: false,
+            fail_on_warning: false,
+            compression: None,
+            comment_prefix: None,
+            template: None,
+            line_ending: LineEnding::Lf,
+            output_format: OutputFormat::Text,
+            scheme: Scheme::Any,
+            include_subdomains: false,
+            jobs: 1,
+            progress: false,
+            log_level: LogLevel::Normal,
+        }
+    }
+}
+
+#[derive(EnumString, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AutoHeaderField {
+    /// `Last modified: <UTC timestamp>`
+    #[strum(serialize = "last-modified")]
+    LastModified,
+    /// `Entries: <number of domain, path, IP, and CIDR entries>`
+    #[strum(serialize = "entries")]
+    Entries,
+}
+
+impl AutoHeaderField {
+    pub const ALL: [Self; 2] = [Self::LastModified, Self::Entries];
+
+    fn attribute(self, entries: usize) -> HeaderAttribute {
+        match self {
+            Self::LastModified => {
+                let now = OffsetDateTime::now_utc();
+                HeaderAttribute {
+                    key: "Last modified".to_string(),
+                    value: format!(
+                        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                        now.year(), u8::from(now.month()), now.day(), now.hour(), now.minute(), now.second(),
+                    ),
+                }
+            }
+            Self::Entries => HeaderAttribute {
+                key: "Entries".to_string(),
+                value: entries.to_string(),
+            },
+        }
+    }
+}
+
+#[derive(EnumString, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum DedupMode {
+    /// Removes entries which have the same type, match method, and value as a preceding entry.
+    #[strum(serialize = "exact")]
+    Exact,
+    /// Also removes entries covered by a wildcard domain, such as `sub.example.com` by `*.example.com`.
+    #[strum(serialize = "semantic")]
+    Semantic,
+}
+
+#[derive(EnumString, Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum HeaderDupPolicy {
+    /// Fails with [`CompileError::DuplicateHeader`].
+    #[strum(serialize = "error")]
+    Error,
+    /// Prints a warning and keeps the first occurrence.
+    #[strum(serialize = "warn")]
+    Warn,
+    /// Keeps the last occurrence.
+    #[strum(serialize = "last-wins")]
+    LastWins,
+    /// Emits every occurrence.
+    #[default]
+    #[strum(serialize = "keep-all")]
+    KeepAll,
+}
+
+/// How much is printed to stderr. Fatal errors are returned to the caller instead.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Default)]
+pub enum LogLevel {
+    /// Prints errors only.
+    Quiet,
+    /// Prints warnings.
+    #[default]
+    Normal,
+    /// Prints warnings and progress messages.
+    Verbose,
+}
+
+impl LogLevel {
+    /// Prints `message` to stderr at every level. For errors which do not stop the run, such as a failed `batch` job.
+    #[allow(clippy::unused_self)]
+    pub fn error(self, message: impl Display) {
+        eprintln!("{message}");
+    }
+
+    /// Prints `message` to stderr unless [`LogLevel::Quiet`].
+    pub fn warn(self, message: impl Display) {
+        if self >= Self::Normal {
+            eprintln!("{message}");
+        }
+    }
+
+    /// Prints `message` to stderr unless [`LogLevel::Quiet`]. For results, such as a finished `batch` job.
+    pub fn info(self, message: impl Display) {
+        if self >= Self::Normal {
+            eprintln!("{message}");
+        }
+    }
+
+    /// Prints `message` to stderr if [`LogLevel::Verbose`].
+    pub fn verbose(self, message: impl Display) {
+        if self >= Self::Verbose {
+            eprintln!("{message}");
+        }
+    }
+}
+
+/// Prints warnings according to [`LogLevel`], and records them for [`CompileStats::warnings`].
+struct Diagnostics {
+    log_level: LogLevel,
+    warnings: Mutex<Vec<String>>,
+}
+
+impl Diagnostics {
+    const fn new(log_level: LogLevel) -> Self {
+        Self {
+            log_level,
+            warnings: Mutex::new(vec![]),
+        }
+    }
+
+    /// Printed unless [`LogLevel::Quiet`].
+    fn warn(&self, message: String) {
+        self.log_level.warn(format_args!("warning: {message}"));
+        self.record(message);
+    }
+
+    /// Printed only with [`LogLevel::Verbose`], since there may be one for each entry.
+    fn verbose_warn(&self, message: String) {
+        self.log_level.verbose(format_args!("warning: {message}"));
+        self.record(message);
+    }
+
+    fn record(&self, message: String) {
+        self.warnings.lock().unwrap_or_else(PoisonError::into_inner).push(message);
+    }
+
+    fn into_warnings(self) -> Vec<String> {
+        self.warnings.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[derive(EnumString, strum::Display, Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Scheme {
+    #[default]
+    #[strum(serialize = "any")]
+    Any,
+    #[strum(serialize = "https")]
+    Https,
+    #[strum(serialize = "http")]
+    Http,
+}
+
+impl Scheme {
+    /// Scheme part of a match pattern, such as `*` of `*://example.com/*`.
+    const fn match_pattern(self) -> &'static str {
+        match self {
+            Self::Any => "*",
+            Self::Https => "https",
+            Self::Http => "http",
+        }
+    }
+}
+
+#[derive(EnumString, Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum LineEnding {
+    #[default]
+    #[strum(serialize = "lf")]
+    Lf,
+    /// Expected by some Windows tools.
+    #[strum(serialize = "crlf")]
+    Crlf,
+}
+
+#[derive(EnumString, Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum OutputFormat {
+    /// Filter list of the target.
+    #[default]
+    #[strum(serialize = "text")]
+    Text,
+    /// One JSON object per rule, such as `{"rule": "||example.com^", "source_type": "domain", "section": "Base"}`.
+    /// Headers, comments, and the prepended and appended files are omitted.
+    #[strum(serialize = "json-lines")]
+    JsonLines,
+}
+
+#[derive(EnumString, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum OutputCompression {
+    #[strum(serialize = "gzip")]
+    Gzip,
+}
+
+/// Number of entries and generated lines of a compile run.
+#[derive(Default, Clone, Eq, PartialEq, Debug, Serialize)]
+pub struct CompileStats {
+    /// Entries loaded from the inputs, before any filter.
+    pub input_entries: usize,
+    pub domain_entries: usize,
+    pub path_entries: usize,
+    pub ip_entries: usize,
+    pub cidr_entries: usize,
+    pub regex_entries: usize,
+    pub header_lines: usize,
+    /// Lines generated by [`GenerateTargetPlatform::Base`].
+    pub base_lines: usize,
+    /// Lines generated by the search engine feature flags, keyed by the engine name such as `Google`.
+    /// Engines which are not requested are absent.
+    pub search_lines: BTreeMap<&'static str, usize>,
+    /// Lines of [`CompileOptions::append_file`].
+    pub footer_lines: usize,
+    /// Warnings of the run, including ones printed only with [`LogLevel::Verbose`].
+    pub warnings: Vec<String>,
+}
+
+/// Machine readable summary of a compile run.
+#[derive(Serialize, Debug)]
+pub struct CompileReport<'a> {
+    pub target: CompileTarget,
+    pub feature_flags: &'a [GenerateTargetPlatform],
+    #[serde(flatten)]
+    pub stats: &'a CompileStats,
+}
+
+impl CompileStats {
+    #[must_use]
+    pub fn total_lines(&self) -> usize {
+        self.header_lines + self.base_lines + self.search_lines.values().sum::<usize>() + self.footer_lines
+    }
+
+}
+
+impl Display for CompileStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "domain entries: {}", self.domain_entries)?;
+        writeln!(f, "path entries: {}", self.path_entries)?;
+        writeln!(f, "IP entries: {}", self.ip_entries)?;
+        writeln!(f, "CIDR entries: {}", self.cidr_entries)?;
+        writeln!(f, "regex entries: {}", self.regex_entries)?;
+        writeln!(f, "header lines: {}", self.header_lines)?;
+        writeln!(f, "Base lines: {}", self.base_lines)?;
+        for spec in &SEARCH_ENGINES {
+            writeln!(f, "{} lines: {}", spec.name, self.search_lines.get(spec.name).copied().unwrap_or_default())?;
+        }
+        writeln!(f, "footer lines: {}", self.footer_lines)?;
+        writeln!(f, "total lines: {}", self.total_lines())
+    }
+}
+
+/// Compiles `input_files` and writes the result to `output_file`. `-` means stdout.
+/// Nothing is written if no feature flag is given.
+///
+/// # Errors
+/// Returns an error if an input can not be loaded, the feature combination is unsupported, or writing fails.
+pub fn compile(
+    input_files: &[PathBuf],
+    output_file: &Path,
+    options: &CompileOptions,
+) -> Result<CompileStats, CompileError> {
+    let open = || {
+        options.log_level.verbose("writing file");
+
+        open_output(output_file)
+    };
+
+    match options.compression {
+        None => {
+            let mut writer = LazyWriter::new(open);
+            let Some((stats, _)) = generate(Source::Files(input_files), options, &mut writer)? else {
+                return Ok(CompileStats::default())
+            };
+            // creates the file even if the output is empty
+            writer.into_inner()?.flush()?;
+
+            Ok(stats)
+        }
+        Some(OutputCompression::Gzip) => {
+            let mut writer = LazyWriter::new(|| Ok(GzEncoder::new(open()?, flate2::Compression::default())));
+            let Some((stats, _)) = generate(Source::Files(input_files), options, &mut writer)? else {
+                return Ok(CompileStats::default())
+            };
+            // writes the gzip trailer
+            writer.into_inner()?.finish()?.flush()?;
+
+            Ok(stats)
+        }
+    }
+}
+
+/// Compiles `input_files` and writes the result into numbered files of at most `lines_per_file` generated lines.
+///
+/// `out.txt` is split into `out.1.txt`, `out.2.txt`, and so on. Header lines are repeated in every file, and the
+/// footer is written to the last one. Nothing is written if no feature flag is given.
+/// Returns the number of written files as well.
+///
+/// # Errors
+/// Returns an error if an input can not be loaded, the feature combination is unsupported, or writing fails.
+pub fn compile_split(
+    input_files: &[PathBuf],
+    output_file: &Path,
+    options: &CompileOptions,
+    lines_per_file: NonZeroUsize,
+) -> Result<(CompileStats, usize), CompileError> {
+    let mut buf = vec![];
+    let Some((stats, _)) = generate(Source::Files(input_files), options, &mut buf)? else {
+        return Ok((CompileStats::default(), 0))
+    };
+    let lines = buf.split_inclusive(|&b| b == b'\n').collect::<Vec<_>>();
+    let (header, rest) = lines.split_at(stats.header_lines.min(lines.len()));
+    let (body, footer) = rest.split_at(rest.len().saturating_sub(stats.footer_lines));
+
+    let mut chunks = body.chunks(lines_per_file.get()).collect::<Vec<_>>();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        let footer = if i + 1 == chunks.len() { footer } else { &[] };
+        let content = header.iter().chain(*chunk).chain(footer).copied().collect::<Vec<_>>().concat();
+        let path = numbered_path(output_file, i + 1);
+        options.log_level.verbose(format_args!("writing {}", path.display()));
+
+        let mut writer = open_output(&path)?;
+        match options.compression {
+            None => {
+                writer.write_all(&content)?;
+                writer.flush()?;
+            }
+            Some(OutputCompression::Gzip) => {
+                let mut writer = GzEncoder::new(writer, flate2::Compression::default());
+                writer.write_all(&content)?;
+                writer.finish()?.flush()?;
+            }
+        }
+    }
+
+    options.log_level.verbose(format_args!("wrote {} files", chunks.len()));
+
+    Ok((stats, chunks.len()))
+}
+
+/// `out.txt.gz` -> `out.{n}.txt.gz`
+fn numbered_path(path: &Path, n: usize) -> PathBuf {
+    let name = path.file_name().map_or(Cow::Borrowed(""), |x| x.to_string_lossy());
+    let name = name.split_once('.').map_or_else(|| format!("{name}.{n}"), |(stem, extension)| format!("{stem}.{n}.{extension}"));
+
+    path.with_file_name(name)
+}
+
+/// Compiles `entries`, such as ones read by [`read_ndjson_entries`], and writes the result to `writer`.
+///
+/// Entries are validated as if loaded from a file. [`CompileOptions::input_format`] is not used.
+/// Every entry is collected before compiling, since dedup and sort need all of them.
+/// Returns the number of written bytes.
+///
+/// # Errors
+/// Returns an error if an entry is invalid, the feature combination is unsupported, or writing fails.
+pub fn compile_entries<I: IntoIterator<Item = Entry>, W: Write>(
+    entries: I,
+    writer: &mut W,
+    options: &CompileOptions,
+) -> Result<usize, CompileError> {
+    let bytes = generate(Source::List(EntryList(entries.into_iter().collect())), options, &mut *writer)?.map_or(0, |(_, bytes)| bytes);
+    writer.flush()?;
+
+    Ok(bytes)
+}
+
+/// Compiles `input_files` and writes the result to `writer`.
+/// Returns the number of written bytes.
+///
+/// # Errors
+/// Returns an error if an input can not be loaded, the feature combination is unsupported, or writing fails.
+pub fn compile_to_writer<W: Write>(
+    input_files: &[PathBuf],
+    writer: &mut W,
+    options: &CompileOptions,
+) -> Result<usize, CompileError> {
+    let bytes = generate(Source::Files(input_files), options, &mut *writer)?.map_or(0, |(_, bytes)| bytes);
+    writer.flush()?;
+
+    Ok(bytes)
+}
+
+/// Compiles `input_files` into memory, without compression.
+/// The output is empty if no feature flag is given.
+///
+/// # Errors
+/// Returns an error if an input can not be loaded or the feature combination is unsupported.
+pub fn compile_to_bytes(
+    input_files: &[PathBuf],
+    options: &CompileOptions,
+) -> Result<(Vec<u8>, CompileStats), CompileError> {
+    let mut buf = vec![];
+    let stats = generate(Source::Files(input_files), options, &mut buf)?.map(|(stats, _)| stats).unwrap_or_default();
+
+    Ok((buf, stats))
+}
+
+/// Runs the whole compile pipeline without writing anything.
+///
+/// # Errors
+/// Returns an error if an input can not be loaded or the feature combination is unsupported.
+pub fn compile_stats(
+    input_files: &[PathBuf],
+    options: &CompileOptions,
+) -> Result<CompileStats, CompileError> {
+    Ok(generate(Source::Files(input_files), options, std::io::sink())?.map(|(stats, _)| stats).unwrap_or_default())
+}
+
+/// Compiles `list` into a string without accessing the file system, such as on `wasm32`.
+/// Nothing is generated if no feature flag is given.
+///
+/// # Errors
+/// Returns an error if an entry is invalid or the feature combination is unsupported.
+pub fn compile_to_string(
+    list: &EntryList,
+    target: CompileTarget,
+    feature_flags: &[GenerateTargetPlatform],
+    header_attributes: &[HeaderAttribute],
+) -> Result<String, CompileError> {
+    let options = CompileOptions {
+        header_attributes: header_attributes.to_vec(),
+        ..CompileOptions::new(target, feature_flags.to_vec())
+    };
+    let mut buf = vec![];
+    generate(Source::List(list.clone()), &options, &mut buf)?;
+
+    // every line is built from strings
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// Where [`generate`] loads entries from.
+enum Source<'a> {
+    Files(&'a [PathBuf]),
+    /// Validated as if loaded from a file.
+    List(EntryList),
+}
+
+/// Writes each line to `writer` as soon as it is serialized.
+/// Returns the stats and the number of written bytes, or `None` if there is nothing to generate.
+/// Nothing is written to `writer` if this fails before the first rule.
+#[allow(clippy::too_many_lines)]
+fn generate<W: Write>(
+    source: Source<'_>,
+    options: &CompileOptions,
+    writer: W,
+) -> Result<Option<(CompileStats, usize)>, CompileError> {
+    let &CompileOptions {
+        input_format,
+        trim_whitespace,
+        target,
+        ref feature_flags,
+        ref header_attributes,
+        ref header_file,
+        ref auto_header,
+        header_dup_policy,
+        expand_env,
+        ref prepend_file,
+        ref append_file,
+        ref adguard_modifiers,
+        max_entries,
+        include_disabled,
+        modified_after,
+        ref only_types,
+        expand_cidr,
+        lowercase,
+        normalize_trailing_dot,
+        punycode,
+        strip_www,
+        dedup,
+        sort,
+        group_by_type,
+        rule_limit_per_line,
+        emit_metadata_comment,
+        fail_on_empty,
+        fail_on_warning,
+        compression: _,
+        ref comment_prefix,
+        ref template,
+        line_ending,
+        output_format,
+        scheme,
+        include_subdomains,
+        jobs,
+        progress,
+        log_level,
+    } = options;
+
+    // each feature flag generates its own section, including Base
+    if feature_flags.is_empty() {
+        if fail_on_empty {
+            return Err(CompileError::NothingToGenerate)
+        }
+
+        return Ok(None)
+    }
+
+    if let Some((first, second)) = conflicting_feature_flags(feature_flags) {
+        return Err(CompileError::ConflictingFeatures { first, second })
+    }
+
+    let mut search_engines = vec![];
+    for spec in SEARCH_ENGINES {
+        let prefix = feature_flags.contains(&spec.prefix);
+        let fuzzy = feature_flags.contains(&spec.fuzzy);
+
+        let targets = if prefix { spec.prefix_targets } else { spec.fuzzy_targets };
+        if (prefix || fuzzy) && !targets.contains(&target) {
+            return Err(CompileError::UnsupportedFeatureSet)
+        }
+
+        if prefix || fuzzy {
+            search_engines.push((spec, prefix));
+        }
+    }
+
+    if target != CompileTarget::AdGuard && !adguard_modifiers.is_empty() {
+        return Err(CompileError::UnsupportedFeatureSet)
+    }
+
+    if let Some(template) = template {
+        check_template(template)?;
+    }
+
+    let file_header_attributes = header_file.as_deref().map(read_header_file).transpose()?.unwrap_or_default();
+    let header_attributes = file_header_attributes.into_iter()
+        .chain(header_attributes.iter().cloned())
+        .map(|x| if expand_env { expand_env_vars(x) } else { Ok(x) })
+        .collect::<Result<Vec<_>, _>>()?;
+    let prepend = prepend_file.as_deref().map(read_auxiliary_file).transpose()?;
+    let append = append_file.as_deref().map(read_auxiliary_file).transpose()?;
+
+    let diagnostics = Diagnostics::new(log_level);
+    if scheme != Scheme::Any && target != CompileTarget::UBlackList {
+        diagnostics.warn(format!("ignored scheme '{scheme}'; only uBlackList rules can restrict the scheme"));
+    }
+
+    let mut list = match source {
+        Source::Files(input_files) => load_entries_with(input_files, input_format, trim_whitespace, &diagnostics)?,
+        Source::List(mut list) => {
+            if trim_whitespace {
+                trim_values(&mut list, &diagnostics)?;
+            }
+            check_entries(&list, &diagnostics)?;
+            list
+        }
+    };
+    let input_entries = list.0.len();
+    log_level.verbose(format_args!("loaded {} entries", list.0.len()));
+
+    if let Some(limit) = max_entries.filter(|limit| list.0.len() > *limit) {
+        return Err(CompileError::TooManyEntries {
+            count: list.0.len(),
+            limit,
+        })
+    }
+
+    if !include_disabled {
+        let before = list.0.len();
+        list.0.retain(Entry::is_enabled);
+        log_level.verbose(format_args!("skipped {} disabled entries", before - list.0.len()));
+    }
+
+    let before = list.0.len();
+    list.0.retain(|x| x.is_active_for(feature_flags));
+    if before != list.0.len() {
+        log_level.verbose(format_args!("skipped {} entries not for the given feature flags", before - list.0.len()));
+    }
+
+    if let Some(modified_after) = modified_after {
+        let before = list.0.len();
+        // syntax_check has validated every date
+        list.0.retain(|x| x.added().and_then(|x| x.parse::<IsoDate>().ok()).is_some_and(|x| x >= modified_after));
+        log_level.verbose(format_args!("skipped {} entries not added on or after {modified_after}", before - list.0.len()));
+    }
+
+    if !only_types.is_empty() {
+        let before = list.0.len();
+        list.0.retain(|x| only_types.contains(&x.entry_type()));
+        log_level.verbose(format_args!("skipped {} entries of other types", before - list.0.len()));
+    }
+
+    if expand_cidr {
+        list.0 = expand_cidr_entries(list.0)?;
+    }
+
+    if lowercase {
+        lowercase_hosts(&mut list);
+    }
+
+    if normalize_trailing_dot {
+        let stripped = strip_trailing_dot(&mut list);
+        log_level.verbose(format_args!("stripped trailing dot from {stripped} entries"));
+    }
+
+    if punycode {
+        punycode_hosts(&mut list)?;
+    }
+
+    if strip_www {
+        let stripped = strip_www_prefix(&mut list);
+        log_level.verbose(format_args!("stripped 'www.' from {stripped} entries"));
+    }
+
+    match dedup {
+        None => {}
+        Some(DedupMode::Exact) => {
+            let removed = dedup_entries(&mut list);
+            log_level.verbose(format_args!("removed {removed} duplicated entries"));
+        }
+        Some(DedupMode::Semantic) => {
+            // search engine rules are generated only from literal entries
+            let wildcard_covers = target.wildcard_matches_subdomains() && search_engines.is_empty();
+            if !wildcard_covers {
+                log_level.verbose("wildcard entries do not cover other entries for this target and feature flags");
+            }
+
+            let removed = semantic_dedup_entries(&mut list, wildcard_covers);
+            for (value, covering) in &removed {
+                if value == covering {
+                    log_level.verbose(format_args!("removed duplicated '{value}'"));
+                } else {
+                    log_level.verbose(format_args!("removed '{value}', covered by '{covering}'"));
+                }
+            }
+            log_level.verbose(format_args!("removed {} covered entries", removed.len()));
+        }
+    }
+
+    if sort {
+        list.0.sort_by_cached_key(|x| x.value().to_lowercase());
+    }
+
+    let comment = comment_prefix.as_deref().unwrap_or_else(|| target.comment_prefix());
+    if !comment.starts_with(target.comment_prefix()) {
+        diagnostics.warn(format!("comment prefix '{comment}' may not be recognized as a comment by {target}; expected a prefix starting with '{}'", target.comment_prefix()));
+    }
+
+    // jobs = 1 serializes on the current thread
+    let pool = if jobs == 1 {
+        None
+    } else {
+        Some(ThreadPoolBuilder::new().num_threads(jobs).build()?)
+    };
+
+    let mut stats = CompileStats {
+        input_entries,
+        domain_entries: list.0.iter().filter(|x| matches!(x, Entry::Domain { .. })).count(),
+        path_entries: list.0.iter().filter(|x| matches!(x, Entry::Path { .. })).count(),
+        ip_entries: list.0.iter().filter(|x| matches!(x, Entry::Ip { .. })).count(),
+        cidr_entries: list.0.iter().filter(|x| matches!(x, Entry::Cidr { .. })).count(),
+        regex_entries: list.0.iter().filter(|x| matches!(x, Entry::Regex { .. })).count(),
+        ..CompileStats::default()
+    };
+
+    let header_attributes = header_attributes.into_iter()
+        .chain(auto_header.iter().map(|x| x.attribute(stats.domain_entries + stats.path_entries + stats.ip_entries + stats.cidr_entries + stats.regex_entries)))
+        .collect::<Vec<_>>();
+    let header_attributes = apply_header_dup_policy(header_attributes, header_dup_policy, &diagnostics)?;
+    stats.header_lines = header_attributes.len();
+
+    let mut header = String::new();
+    if let Some(magic) = target.magic_line() {
+        header.push_str(magic);
+        header.push('\n');
+        stats.header_lines += 1;
+    }
+
+    if let Some(prepend) = &prepend {
+        header.push_str(prepend);
+        stats.header_lines += prepend.lines().count();
+    }
+
+    header.extend(header_attributes.iter().map(|x| {
+        let mut buf = String::with_capacity(determine_header_attribute_length(x));
+        buf.push_str(comment);
+        buf.push(' ');
+        buf.push_str(&x.key);
+        buf.push_str(": ");
+        buf.push_str(&x.value);
+        buf.push('\n');
+
+        buf
+    }));
+
+    let append = if output_format == OutputFormat::JsonLines {
+        if !header_attributes.is_empty() || prepend.is_some() || append.is_some() {
+            diagnostics.warn("ignored the header and the prepended and appended files; json-lines has rules only".to_string());
+        }
+
+        header.clear();
+        stats.header_lines = 0;
+        None
+    } else {
+        append
+    };
+
+    let base = feature_flags.contains(&GenerateTargetPlatform::Base);
+    let href_specs = literal_href_specs(&list).collect::<Vec<_>>();
+
+    let progress = if progress && log_level != LogLevel::Quiet {
+        // a draw target on stderr is hidden if it is not a terminal
+        let total = if base { list.0.len() } else { 0 } + search_engines.len() * href_specs.len();
+        ProgressBar::new(total as u64).with_style(ProgressStyle::with_template("{bar:40} {pos}/{len} entries ({eta})").expect("valid template"))
+    } else {
+        ProgressBar::hidden()
+    };
+
+    let line_group = match (rule_limit_per_line, target.line_grouping()) {
+        (Some(_), _) if template.is_some() => {
+            diagnostics.warn("ignored --rule-limit-per-line; rules of a template can not share a line".to_string());
+            None
+        }
+        (Some(_), _) if output_format == OutputFormat::JsonLines => {
+            diagnostics.warn("ignored --rule-limit-per-line; json-lines has one rule per line".to_string());
+            None
+        }
+        (Some(limit), Some(grouping)) => Some(LineGroup { grouping, limit, values: vec![] }),
+        (Some(_), None) => {
+            diagnostics.warn(format!("ignored --rule-limit-per-line; {target} accepts one value per line"));
+            None
+        }
+        (None, _) => None,
+    };
+
+    let mut writer = RuleWriter {
+        inner: writer,
+        pending_header: header,
+        line_ending,
+        output_format,
+        line_group,
+        progress,
+        hold_line_break: false,
+        held_line_break: false,
+        bytes: 0,
+    };
+
+    if let Some(template) = template.as_ref().filter(|_| base) {
+        stats.base_lines = write_base_entries(&mut writer, &list.0, pool.as_ref(), target, true, comment, group_by_type, emit_metadata_comment, &diagnostics, |x| match x {
+            Entry::Comment { text } => Some(format!("{comment} {text}\n")),
+            _ if x.is_exception() => {
+                diagnostics.verbose_warn(format!("skipped exception entry '{}'; template can not express exception", x.value()));
+                None
+            }
+            _ => Some(format!("{}\n", template.replace("{value}", x.value()))),
+        })?;
+    } else if base {
+        stats.base_lines = match target {
+            CompileTarget::UBlackList => {
+                /*
+                jq -r '.[] | select(.type == "domain") | .domain | ("*://" + . + "/*")' < "$data" >> "$dist"
+                jq -r '.[] | select(.type == "path") | .path | ("*://" + .)' < "$data" >> "$dist"
+
+                */ */
+
+                let scheme = scheme.match_pattern();
+                write_base_entries(&mut writer, &list.0, pool.as_ref(), target, false, comment, group_by_type, emit_metadata_comment, &diagnostics, |x| Some(match x {
+                    Entry::Domain { match_method, domain, attributes, .. } => {
+                        let exception = if attributes.exception { "@" } else { "" };
+                        match *match_method {
+                            MatchMethod::Literal if include_subdomains => format!("{exception}{scheme}://{domain}/*\n{exception}{scheme}://*.{domain}/*\n"),
+                            // syntax_check rejects prefix
+                            MatchMethod::Literal | MatchMethod::Wildcard | MatchMethod::Prefix => format!("{exception}{scheme}://{domain}/*\n"),
+                            MatchMethod::Regex => format!("{exception}{}\n", host_regex_rule(domain)),
+                            MatchMethod::Substring => format!("{exception}{scheme}://*{domain}*\n"),
+                            MatchMethod::Suffix => format!("{exception}{}\n", host_suffix_regex_rule(domain)),
+                        }
+                    }
+                    Entry::Path { match_method, path, attributes, .. } => {
+                        let exception = if attributes.exception { "@" } else { "" };
+                        match *match_method {
+                            MatchMethod::Literal | MatchMethod::Wildcard => format!("{exception}{scheme}://{path}\n"),
+                            MatchMethod::Prefix if path.starts_with('/') => format!("{exception}{scheme}://*{path}*\n"),
+                            MatchMethod::Prefix => format!("{exception}{scheme}://{path}*\n"),
+                            MatchMethod::Regex => format!("{exception}/{path}/\n"),
+                            MatchMethod::Substring => format!("{exception}{scheme}://*{path}*\n"),
+                            MatchMethod::Suffix => format!("{exception}{scheme}://*/*{path}\n"),
+                        }
+                    }
+                    Entry::Ip { match_method, ip, attributes, .. } => {
+                        let exception = if attributes.exception { "@" } else { "" };
+                        match *match_method {
+                            MatchMethod::Regex => format!("{exception}{}\n", host_regex_rule(ip)),
+                            // syntax_check rejects the others
+                            _ => format!("{exception}{scheme}://{}/*\n", url_host(ip)),
+                        }
+                    }
+                    Entry::Cidr { cidr, attributes, .. } => {
+                        let exception = if attributes.exception { "@" } else { "" };
+                        let Some(pattern) = cidr_host_pattern(cidr) else {
+                            diagnostics.verbose_warn(format!("skipped CIDR entry '{cidr}'; only octet-aligned IPv4 ranges can be expressed without --expand-cidr"));
+                            return None
+                        };
+                        format!("{exception}{}\n", host_regex_rule(&pattern))
+                    }
+                    Entry::Regex { pattern, .. } => format!("{}\n", slash_escaped_regex_rule(pattern)),
+                    Entry::Comment { text } => format!("{comment} {text}\n"),
+                }))?
+            }
+            CompileTarget::UBlockOrigin | CompileTarget::AdblockPlus | CompileTarget::Brave => {
+                write_base_entries(&mut writer, &list.0, pool.as_ref(), target, false, comment, group_by_type, emit_metadata_comment, &diagnostics, |x| network_filter_rule(x, comment, &diagnostics))?
+            }
+            CompileTarget::Hosts => {
+                let skipped_ips = AtomicUsize::new(0);
+                let written = write_base_entries(&mut writer, &list.0, pool.as_ref(), target, false, comment, group_by_type, emit_metadata_comment, &diagnostics, |x| match x {
+                    Entry::Domain { domain, attributes: EntryAttributes { exception: true, .. }, .. } => {
+                        diagnostics.verbose_warn(format!("skipped exception entry '{domain}'; hosts file can not express exception"));
+                        None
+                    }
+                    Entry::Domain { match_method, domain, attributes: EntryAttributes { exception: false, .. }, .. } => {
+                        match *match_method {
+                            MatchMethod::Literal => Some(format!("0.0.0.0 {domain}\n")),
+                            MatchMethod::Regex | MatchMethod::Wildcard | MatchMethod::Substring | MatchMethod::Suffix | MatchMethod::Prefix => {
+                                diagnostics.verbose_warn(format!("skipped {match_method} entry '{domain}'; hosts file can not express {match_method}"));
+                                None
+                            }
+                        }
+                    }
+                    Entry::Path { path, .. } => {
+                        diagnostics.verbose_warn(format!("skipped path entry '{path}'; hosts file can not express path"));
+                        None
+                    }
+                    Entry::Ip { .. } | Entry::Cidr { .. } => {
+                        skipped_ips.fetch_add(1, Ordering::Relaxed);
+                        None
+                    }
+                    Entry::Regex { pattern, .. } => {
+                        diagnostics.verbose_warn(format!("skipped regex entry '{pattern}'; hosts file can not express regex"));
+                        None
+                    }
+                    Entry::Comment { text } => Some(format!("{comment} {text}\n")),
+                })?;
+
+                // nothing else blocks these addresses, so this is not verbose
+                let skipped_ips = skipped_ips.into_inner();
+                if skipped_ips > 0 {
+                    diagnostics.warn(format!("skipped {skipped_ips} IP and CIDR entries; hosts file can not block IP address"));
+                }
+
+                written
+            }
+            CompileTarget::AdGuard => {
+                let modifiers = if adguard_modifiers.is_empty() {
+                    String::new()
+                } else {
+                    let joined = adguard_modifiers.iter().map(AdGuardModifier::serialize).collect::<Vec<_>>().join(",");
+                    format!("${joined}")
+                };
+
+                write_base_entries(&mut writer, &list.0, pool.as_ref(), target, false, comment, group_by_type, emit_metadata_comment, &diagnostics, |x| match x {
+                    Entry::Domain { match_method, domain, attributes, .. } => {
+                        let exception = if attributes.exception { "@@" } else { "" };
+                        match *match_method {
+                            // syntax_check rejects prefix
+                            MatchMethod::Literal | MatchMethod::Prefix => Some(format!("{exception}||{domain}^{modifiers}\n")),
+                            MatchMethod::Regex => Some(format!("{exception}/(?:^|\\.)(?:{domain})$/{modifiers}\n")),
+                            MatchMethod::Wildcard => Some(format!("{exception}||{}^{modifiers}\n", strip_wildcard_subdomain(domain))),
+                            MatchMethod::Substring => Some(format!("{exception}*{domain}*{modifiers}\n")),
+                            // without '||', the host name may have any prefix
+                            MatchMethod::Suffix => Some(format!("{exception}{domain}^{modifiers}\n")),
+                        }
+                    }
+                    Entry::Path { path, .. } => {
+                        diagnostics.verbose_warn(format!("skipped path entry '{path}'; AdGuard DNS filter can not express path"));
+                        None
+                    }
+                    Entry::Ip { match_method, ip, attributes, .. } => {
+                        // matches the IP address in DNS responses
+                        let exception = if attributes.exception { "@@" } else { "" };
+                        match *match_method {
+                            MatchMethod::Regex => Some(format!("{exception}/^(?:{ip})$/{modifiers}\n")),
+                            // syntax_check rejects the others
+                            _ => Some(format!("{exception}||{ip}^{modifiers}\n")),
+                        }
+                    }
+                    Entry::Cidr { cidr, .. } => {
+                        diagnostics.verbose_warn(format!("skipped CIDR entry '{cidr}'; AdGuard DNS filter can not express CIDR without --expand-cidr"));
+                        None
+                    }
+                    Entry::Regex { pattern, .. } => {
+                        diagnostics.verbose_warn(format!("skipped regex entry '{pattern}'; AdGuard DNS filter matches host names, not URLs"));
+                        None
+                    }
+                    Entry::Comment { text } => Some(format!("{comment} {text}\n")),
+                })?
+            }
+            CompileTarget::Dnsmasq | CompileTarget::SmartDns => {
+                let (prefix, suffix) = target.domain_rule_format().expect("dnsmasq and SmartDNS have a domain rule format");
+                let skipped = AtomicUsize::new(0);
+                let skipped_ips = AtomicUsize::new(0);
+                let written = write_base_entries(&mut writer, &list.0, pool.as_ref(), target, false, comment, group_by_type, emit_metadata_comment, &diagnostics, |x| match x {
+                    Entry::Domain { match_method: MatchMethod::Literal | MatchMethod::Wildcard, domain, attributes: EntryAttributes { exception: false, .. }, .. } => {
+                        Some(format!("{prefix}{}{suffix}\n", strip_wildcard_subdomain(domain)))
+                    }
+                    Entry::Domain { match_method: MatchMethod::Regex | MatchMethod::Substring | MatchMethod::Suffix | MatchMethod::Prefix, .. } | Entry::Domain { attributes: EntryAttributes { exception: true, .. }, .. } | Entry::Path { .. } | Entry::Regex { .. } => {
+                        skipped.fetch_add(1, Ordering::Relaxed);
+                        None
+                    }
+                    Entry::Ip { .. } | Entry::Cidr { .. } => {
+                        skipped_ips.fetch_add(1, Ordering::Relaxed);
+                        None
+                    }
+                    Entry::Comment { text } => Some(format!("{comment} {text}\n")),
+                })?;
+
+                let skipped = skipped.into_inner();
+                if skipped > 0 {
+                    diagnostics.verbose_warn(format!("skipped {skipped} path, regex, substring, or exception entries; {target} can not express them"));
+                }
+
+                // nothing else blocks these addresses, so this is not verbose
+                let skipped_ips = skipped_ips.into_inner();
+                if skipped_ips > 0 {
+                    diagnostics.warn(format!("skipped {skipped_ips} IP and CIDR entries; {target} can not block IP address"));
+                }
+
+                written
+            }
+        };
+    }
+
+    writer.end_line_group()?;
+
+    for (spec, prefix) in search_engines {
+        let href_operator = href_operator(prefix);
+
+        let section = if prefix { spec.prefix } else { spec.fuzzy };
+        writer.hold_line_break = !spec.final_line_break && output_format == OutputFormat::Text;
+
+        let lines = write_entries(&mut writer, &href_specs, pool.as_ref(), |x| {
+            let rules = spec.rules.iter().fold(String::new(), |mut buf, rule| {
+                buf.push_str(spec.host);
+                buf.push_str("##");
+                buf.push_str(&rule.replace("{operator}", href_operator).replace("{value}", x.value()));
+                buf.push('\n');
+                buf
+            });
+
+            Some(match output_format {
+                OutputFormat::Text => rules,
+                OutputFormat::JsonLines => json_rules(&rules, x, section, x.provenance().filter(|x| emit_metadata_comment && x.is_known())),
+            })
+        })?;
+        stats.search_lines.insert(spec.name, lines);
+    }
+    writer.hold_line_break = false;
+
+    stats.footer_lines = append.as_ref().map_or(0, |x| x.lines().count());
+    stats.warnings = diagnostics.into_warnings();
+
+    log_level.verbose(format_args!("summary:\n{}", stats.to_string().trim_end()));
+
+    if fail_on_empty && stats.total_lines() == stats.header_lines + stats.footer_lines {
+        return Err(CompileError::NothingToGenerate)
+    }
+
+    if let Some(append) = &append {
+        writer.write_lines(append)?;
+    }
+
+    let bytes = writer.finish()?;
+
+    if fail_on_warning && !stats.warnings.is_empty() {
+        return Err(CompileError::WarningsAsErrors {
+            count: stats.warnings.len(),
+        })
+    }
+
+    Ok(Some((stats, bytes)))
+}
+
+/// Base rule of `x` in the network filter syntax shared by uBlock Origin, Adblock Plus, and Brave.
+/// They differ only in [`CompileTarget::magic_line`] and [`CompileTarget::inline_comment_prefix`].
+fn network_filter_rule(x: &Entry, comment: &str, diagnostics: &Diagnostics) -> Option<String> {
+    Some(match x {
+        Entry::Domain { match_method, domain, attributes, .. } => {
+            let exception = if attributes.exception { "@@" } else { "" };
+            match *match_method {
+                // syntax_check rejects prefix
+                MatchMethod::Literal | MatchMethod::Prefix => format!("{exception}||{domain}^\n"),
+                MatchMethod::Regex => format!("{exception}{}\n", host_regex_rule(domain)),
+                MatchMethod::Wildcard => format!("{exception}||{}^\n", strip_wildcard_subdomain(domain)),
+                MatchMethod::Substring => format!("{exception}*{domain}*\n"),
+                MatchMethod::Suffix => format!("{exception}{}\n", host_suffix_regex_rule(domain)),
+            }
+        }
+        Entry::Path { match_method, path, attributes, .. } => {
+            let exception = if attributes.exception { "@@" } else { "" };
+            match *match_method {
+                MatchMethod::Literal => format!("{exception}||{path}^\n"),
+                MatchMethod::Prefix if path.starts_with('/') => format!("{exception}{}\n", path_prefix_regex_rule(path)),
+                MatchMethod::Prefix => format!("{exception}||{path}\n"),
+                MatchMethod::Regex => format!("{exception}/{path}/\n"),
+                MatchMethod::Wildcard => format!("{exception}||{}^\n", strip_wildcard_subdomain(path)),
+                MatchMethod::Substring => format!("{exception}*{path}*\n"),
+                MatchMethod::Suffix => format!("{exception}*{path}|\n"),
+            }
+        }
+        Entry::Ip { match_method, ip, attributes, .. } => {
+            let exception = if attributes.exception { "@@" } else { "" };
+            match *match_method {
+                MatchMethod::Regex => format!("{exception}{}\n", host_regex_rule(ip)),
+                // syntax_check rejects the others
+                _ => format!("{exception}||{}^\n", url_host(ip)),
+            }
+        }
+        Entry::Cidr { cidr, attributes, .. } => {
+            let exception = if attributes.exception { "@@" } else { "" };
+            let Some(pattern) = cidr_host_pattern(cidr) else {
+                diagnostics.verbose_warn(format!("skipped CIDR entry '{cidr}'; only octet-aligned IPv4 ranges can be expressed without --expand-cidr"));
+                return None
+            };
+            format!("{exception}{}\n", host_regex_rule(&pattern))
+        }
+        Entry::Regex { pattern, .. } => format!("/{pattern}/\n"),
+        Entry::Comment { text } => format!("{comment} {text}\n"),
+    })
+}
+
+/// Number of items serialized at once on a thread pool. Bounds the memory held before writing.
+const PARALLEL_CHUNK_SIZE: usize = 4096;
+
+/// Serializes every item with `f` and writes the results in order. Runs on `pool` if given.
+/// Returns the number of written lines.
+fn write_entries<T, F, W>(writer: &mut RuleWriter<W>, items: &[T], pool: Option<&ThreadPool>, f: F) -> std::io::Result<usize>
+where
+    T: Sync,
+    F: Fn(&T) -> Option<String> + Send + Sync,
+    W: Write,
+{
+    let mut lines = 0;
+    if let Some(pool) = pool {
+        for chunk in items.chunks(PARALLEL_CHUNK_SIZE) {
+            let serialized = pool.install(|| chunk.par_iter().filter_map(&f).collect::<String>());
+            lines += writer.write_lines(&serialized)?;
+            writer.progress.inc(chunk.len() as u64);
+        }
+    } else {
+        for item in items {
+            if let Some(serialized) = f(item) {
+                lines += writer.write_lines(&serialized)?;
+            }
+            writer.progress.inc(1);
+        }
+    }
+
+    Ok(lines)
+}
+
+/// [`write_entries`] for the Base section. Attaches the modifiers, the scope, and the note of each entry to its rule.
+/// With `template`, the modifiers and the scope are ignored, since [`CompileOptions::template`] replaces the whole rule.
+/// See [`CompileOptions::group_by_type`] and [`CompileOptions::emit_metadata_comment`] for the flags.
+#[allow(clippy::too_many_arguments)]
+fn write_base_entries<F, W>(
+    writer: &mut RuleWriter<W>,
+    entries: &[Entry],
+    pool: Option<&ThreadPool>,
+    target: CompileTarget,
+    template: bool,
+    comment: &str,
+    group_by_type: bool,
+    emit_metadata_comment: bool,
+    diagnostics: &Diagnostics,
+    f: F,
+) -> std::io::Result<usize>
+where
+    F: Fn(&Entry) -> Option<String> + Send + Sync,
+    W: Write,
+{
+    let output_format = writer.output_format;
+    let serialize = |x: &Entry| {
+        if output_format == OutputFormat::JsonLines && matches!(x, Entry::Comment { .. }) {
+            return None
+        }
+
+        f(x).map(|rule| {
+            let mut options = vec![];
+            match x.modifiers() {
+                Some(_) if template => diagnostics.warn(format!("ignored modifiers of entry '{}'; the template replaces the rule", x.value())),
+                Some(modifiers) if target == CompileTarget::UBlockOrigin => options.extend(modifiers.iter().cloned()),
+                Some(_) => diagnostics.warn(format!("ignored modifiers of entry '{}'; only uBlockOrigin supports modifiers", x.value())),
+                None => {}
+            }
+            match x.scope() {
+                Some(_) if template => diagnostics.warn(format!("ignored scope of entry '{}'; the template replaces the rule", x.value())),
+                Some(scope) if target.supports_domain_option() => options.push(format!("domain={}", scope.join("|"))),
+                Some(_) => diagnostics.warn(format!("ignored scope of entry '{}'; {target} can not express scope", x.value())),
+                None => {}
+            }
+
+            if options.is_empty() {
+                rule
+            } else {
+                format!("{}${}\n", rule.trim_end_matches('\n'), options.join(","))
+            }
+        }).map(|rule| match (output_format, x.note(), target.inline_comment_prefix()) {
+            (OutputFormat::JsonLines, ..) => json_rules(&rule, x, GenerateTargetPlatform::Base, x.provenance().filter(|x| emit_metadata_comment && x.is_known())),
+            (OutputFormat::Text, None, _) => rule,
+            (OutputFormat::Text, Some(note), Some(prefix)) => format!("{} {prefix} {note}\n", rule.trim_end_matches('\n')),
+            (OutputFormat::Text, Some(note), None) => format!("{comment} {note}\n{rule}"),
+        }).map(|rule| match x.provenance().filter(|x| output_format == OutputFormat::Text && emit_metadata_comment && x.is_known()) {
+            Some(provenance) => format!("{comment} source: {provenance}\n{rule}"),
+            None => rule,
+        })
+    };
+
+    if !group_by_type {
+        return write_entries(writer, entries, pool, serialize)
+    }
+
+    for x in entries {
+        if let Entry::Comment { text } = x {
+            diagnostics.verbose_warn(format!("skipped comment entry '{text}'; it can not be placed in a group"));
+            writer.progress.inc(1);
+        }
+    }
+
+    let mut lines = 0;
+    for (entry_type, banner) in [(EntryType::Domain, "Domains"), (EntryType::Path, "Paths"), (EntryType::Ip, "IPs"), (EntryType::Cidr, "CIDRs"), (EntryType::Regex, "Regexes")] {
+        let group = entries.iter().filter(|x| x.entry_type() == entry_type).cloned().collect::<Vec<_>>();
+
+        // the banner is held back like the header, and dropped if no rule of the group is written
+        let pending = writer.pending_header.len();
+        let banner_lines = if output_format == OutputFormat::Text {
+            writer.pending_header.push_str(comment);
+            writer.pending_header.push(' ');
+            writer.pending_header.push_str(banner);
+            writer.pending_header.push('\n');
+            1
+        } else {
+            0
+        };
+
+        let written = write_entries(writer, &group, pool, serialize)?;
+        if written == 0 {
+            writer.pending_header.truncate(pending);
+        } else {
+            lines += written + banner_lines;
+        }
+    }
+
+    Ok(lines)
+}
+
+/// One line of [`OutputFormat::JsonLines`].
+#[derive(Serialize)]
+struct JsonRule<'a> {
+    rule: &'a str,
+    source_type: EntryType,
+    section: GenerateTargetPlatform,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<&'a str>,
+    /// [`Provenance`] with [`CompileOptions::emit_metadata_comment`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+}
+
+/// Wraps each line of `rules`, generated from `entry`, into a [`JsonRule`].
+fn json_rules(rules: &str, entry: &Entry, section: GenerateTargetPlatform, source: Option<&Provenance>) -> String {
+    rules.lines().fold(String::new(), |mut buf, rule| {
+        let line = JsonRule {
+            rule,
+            source_type: entry.entry_type(),
+            section,
+            note: entry.note(),
+            source: source.map(ToString::to_string),
+        };
+        buf.push_str(&serde_json::to_string(&line).expect("JsonRule is always serializable"));
+        buf.push('\n');
+        buf
+    })
+}
+
+/// Writes generated lines to `inner`.
+/// The header is held back until the first rule, so that a run rejected by [`CompileOptions::fail_on_empty`]
+/// writes nothing.
+struct RuleWriter<W> {
+    inner: W,
+    pending_header: String,
+    line_ending: LineEnding,
+    /// Read by [`write_base_entries`].
+    output_format: OutputFormat,
+    /// Joins rules until [`RuleWriter::end_line_group`].
+    line_group: Option<LineGroup>,
+    /// Advanced by [`write_entries`], and cleared by [`RuleWriter::finish`].
+    progress: ProgressBar,
+    /// Holds back the last line break of each write, as set by [`SearchEngineSpec::final_line_break`].
+    hold_line_break: bool,
+    /// Written before the next text, and dropped by [`RuleWriter::finish`].
+    held_line_break: bool,
+    bytes: usize,
+}
+
+impl<W: Write> RuleWriter<W> {
+    /// Writes `lines`, each of which ends with `\n`, and returns the number of written lines.
+    /// With [`RuleWriter::line_group`], rules are held back, and a joined line is counted when it is started.
+    fn write_lines(&mut self, lines: &str) -> std::io::Result<usize> {
+        if lines.is_empty() {
+            return Ok(0)
+        }
+
+        // a banner of --group-by-type follows the rules of the previous group
+        if !self.pending_header.is_empty() {
+            self.flush_line_group()?;
+        }
+
+        let header = std::mem::take(&mut self.pending_header);
+        self.write_raw(&header)?;
+
+        let Some(group) = &mut self.line_group else {
+            self.write_raw(lines)?;
+            return Ok(lines.lines().count())
+        };
+
+        let mut count = 0;
+        let mut buf = String::with_capacity(lines.len());
+        for line in lines.split_inclusive('\n') {
+            if let Some(value) = group.grouping.value(line.trim_end_matches('\n')) {
+                if group.values.is_empty() {
+                    count += 1;
+                }
+
+                group.values.push(value.to_string());
+                if group.values.len() == group.limit.get() {
+                    buf.push_str(&group.take_line());
+                }
+            } else {
+                buf.push_str(&group.take_line());
+                buf.push_str(line);
+                count += 1;
+            }
+        }
+        self.write_raw(&buf)?;
+
+        Ok(count)
+    }
+
+    /// Writes the held back rules as one line.
+    fn flush_line_group(&mut self) -> std::io::Result<()> {
+        let line = self.line_group.as_mut().map(LineGroup::take_line).unwrap_or_default();
+
+        self.write_raw(&line)
+    }
+
+    /// Writes the held back rules, and stops joining rules.
+    fn end_line_group(&mut self) -> std::io::Result<()> {
+        self.flush_line_group()?;
+        self.line_group = None;
+
+        Ok(())
+    }
+
+    /// Writes `text`, replacing every line break with [`RuleWriter::line_ending`].
+    fn write_raw(&mut self, text: &str) -> std::io::Result<()> {
+        if text.is_empty() {
+            return Ok(())
+        }
+
+        let text = if std::mem::take(&mut self.held_line_break) {
+            Cow::Owned(format!("\n{text}"))
+        } else {
+            Cow::Borrowed(text)
+        };
+        let text = match text.strip_suffix('\n').filter(|_| self.hold_line_break) {
+            Some(text) => {
+                self.held_line_break = true;
+                text
+            }
+            None => &*text,
+        };
+
+        let text = match self.line_ending {
+            LineEnding::Lf => Cow::Borrowed(text),
+            LineEnding::Crlf => Cow::Owned(text.split_inclusive('\n').fold(String::with_capacity(text.len()), |mut buf, line| {
+                match line.strip_suffix('\n') {
+                    Some(line) => {
+                        // a prepended file may already use CRLF
+                        buf.push_str(line.strip_suffix('\r').unwrap_or(line));
+                        buf.push_str("\r\n");
+                    }
+                    None => buf.push_str(line),
+                }
+
+                buf
+            })),
+        };
+        self.inner.write_all(text.as_bytes())?;
+        self.bytes += text.len();
+
+        Ok(())
+    }
+
+    /// Writes the header if no rule has been written, and returns the number of written bytes.
+    /// `inner` is not flushed, because flushing a compressor changes its output.
+    fn finish(mut self) -> std::io::Result<usize> {
+        self.progress.finish_and_clear();
+        let header = std::mem::take(&mut self.pending_header);
+        self.write_raw(&header)?;
+
+        Ok(self.bytes)
+    }
+}
+
+/// Format of a rule line carrying one or more values: `{prefix}{value}{separator}{value}...{suffix}`.
+#[derive(Clone, Copy)]
+struct LineGrouping {
+    prefix: &'static str,
+    separator: &'static str,
+    suffix: &'static str,
+}
+
+impl LineGrouping {
+    /// The value of `line` if it is a rule of a single value. A rule with a note is not.
+    fn value(self, line: &str) -> Option<&str> {
+        line.strip_prefix(self.prefix)?
+            .strip_suffix(self.suffix)
+            .filter(|x| !x.is_empty() && !x.contains(self.separator) && !x.contains(char::is_whitespace))
+    }
+}
+
+/// Rules held back by [`RuleWriter`] to be joined into one line.
+struct LineGroup {
+    grouping: LineGrouping,
+    limit: NonZeroUsize,
+    values: Vec<String>,
+}
+
+impl LineGroup {
+    /// Joins and clears the held back values. Empty if there is none.
+    fn take_line(&mut self) -> String {
+        if self.values.is_empty() {
+            return String::new()
+        }
+
+        let line = format!("{}{}{}\n", self.grouping.prefix, self.values.join(self.grouping.separator), self.grouping.suffix);
+        self.values.clear();
+
+        line
+    }
+}
+
+/// Opens the inner writer on the first write, so that no file is created if compiling fails beforehand.
+struct LazyWriter<W, F> {
+    open: Option<F>,
+    inner: Option<W>,
+}
+
+impl<W: Write, F: FnOnce() -> std::io::Result<W>> LazyWriter<W, F> {
+    const fn new(open: F) -> Self {
+        Self {
+            open: Some(open),
+            inner: None,
+        }
+    }
+
+    fn get(&mut self) -> std::io::Result<&mut W> {
+        if let Some(open) = self.open.take() {
+            self.inner = Some(open()?);
+        }
+
+        Ok(self.inner.as_mut().expect("opened above"))
+    }
+
+    fn into_inner(mut self) -> std::io::Result<W> {
+        self.get()?;
+
+        Ok(self.inner.expect("opened above"))
+    }
+}
+
+impl<W: Write, F: FnOnce() -> std::io::Result<W>> Write for LazyWriter<W, F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.get()?.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.as_mut().map_or(Ok(()), Write::flush)
+    }
+}
+
+/// Regex matching every host in `cidr`. `None` unless it is an octet-aligned IPv4 range.
+fn cidr_host_pattern(cidr: &str) -> Option<String> {
+    let (IpAddr::V4(network), prefix) = parse_cidr(cidr)? else {
+        return None
+    };
+
+    if prefix % 8 != 0 {
+        return None
+    }
+
+    let fixed = usize::from(prefix / 8);
+    let octets = network.octets();
+    let pattern = octets[..fixed].iter().map(u8::to_string)
+        .chain(std::iter::repeat_n("[0-9]{1,3}".to_string(), 4 - fixed))
+        .collect::<Vec<_>>()
+        .join(r"\.");
+
+    Some(pattern)
+}
+
+/// The largest CIDR, in number of addresses, which [`CompileOptions::expand_cidr`] expands.
+pub const MAX_CIDR_EXPANSION: u128 = 65536;
+
+/// Replaces every CIDR entry with IP entries of its addresses.
+fn expand_cidr_entries(entries: Vec<Entry>) -> Result<Vec<Entry>, CompileError> {
+    let mut expanded = Vec::with_capacity(entries.len());
+    for x in entries {
+        let Entry::Cidr { cidr, attributes } = x else {
+            expanded.push(x);
+            continue
+        };
+
+        // syntax_check has validated it
+        let (network, prefix) = parse_cidr(&cidr).expect("invalid CIDR");
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let count = 1_u128.checked_shl(bits - u32::from(prefix)).unwrap_or(u128::MAX);
+        if count > MAX_CIDR_EXPANSION {
+            return Err(CompileError::CidrTooLarge {
+                cidr,
+                limit: MAX_CIDR_EXPANSION,
+            })
+        }
+
+        expanded.extend((0..count).map(|offset| {
+            // count is small enough, so this never overflows
+            let ip = match network {
+                IpAddr::V4(x) => IpAddr::V4(Ipv4Addr::from(u32::from(x) + u32::try_from(offset).expect("small offset"))),
+                IpAddr::V6(x) => IpAddr::V6(Ipv6Addr::from(u128::from(x) + offset)),
+            };
+
+            Entry::Ip {
+                match_method: MatchMethod::Literal,
+                ip: ip.to_string(),
+                attributes: attributes.clone(),
+            }
+        }));
+    }
+
+    Ok(expanded)
+}
+
+/// Brackets IPv6 addresses for URL contexts: `::1` -> `[::1]`
+fn url_host(ip: &str) -> Cow<'_, str> {
+    if ip.contains(':') {
+        Cow::Owned(format!("[{ip}]"))
+    } else {
+        Cow::Borrowed(ip)
+    }
+}
+
+/// The first pair of feature flags which can not be used together, such as `GoogleSearchPrefix` and
+/// `GoogleSearchFuzzy`.
+///
+/// Compiling with them fails with [`CompileError::ConflictingFeatures`].
+#[must_use]
+pub fn conflicting_feature_flags(feature_flags: &[GenerateTargetPlatform]) -> Option<(GenerateTargetPlatform, GenerateTargetPlatform)> {
+    SEARCH_ENGINES.iter()
+        .find(|x| feature_flags.contains(&x.prefix) && feature_flags.contains(&x.fuzzy))
+        .map(|x| (x.prefix, x.fuzzy))
+}
+
+/// Cosmetic rules of a search engine, which hide search results linking to an entry.
+/// Each row of [`SEARCH_ENGINES`] adds an engine.
+struct SearchEngineSpec {
+    /// Key of [`CompileStats::search_lines`].
+    name: &'static str,
+    prefix: GenerateTargetPlatform,
+    fuzzy: GenerateTargetPlatform,
+    /// Targets which accept [`SearchEngineSpec::prefix`].
+    prefix_targets: &'static [CompileTarget],
+    /// Targets which accept [`SearchEngineSpec::fuzzy`].
+    fuzzy_targets: &'static [CompileTarget],
+    /// Host pattern before `##`.
+    host: &'static str,
+    /// Selector templates. `{operator}` is replaced with [`href_operator`], and `{value}` with the entry.
+    /// Each template generates one line.
+    rules: &'static [&'static str],
+    /// Whether the last rule ends with a line break. Google rules have been joined with `\n`, so they do not.
+    final_line_break: bool,
+}
+
+const SEARCH_ENGINES: [SearchEngineSpec; 4] = [
+    SearchEngineSpec {
+        name: "Google",
+        prefix: GenerateTargetPlatform::GoogleSearchPrefix,
+        fuzzy: GenerateTargetPlatform::GoogleSearchFuzzy,
+        prefix_targets: &[CompileTarget::UBlockOrigin],
+        fuzzy_targets: &[CompileTarget::UBlockOrigin, CompileTarget::UBlackList],
+        host: "www.google.*",
+        rules: &[
+            r#".g:has(a[href{operator}"{value}")"#,
+            r#".a[href{operator}"{value}"]:upward(1)"#,
+        ],
+        final_line_break: false,
+    },
+    SearchEngineSpec {
+        name: "DuckDuckGo",
+        prefix: GenerateTargetPlatform::DuckDuckGoPrefix,
+        fuzzy: GenerateTargetPlatform::DuckDuckGoFuzzy,
+        prefix_targets: &[CompileTarget::UBlockOrigin],
+        fuzzy_targets: &[CompileTarget::UBlockOrigin],
+        host: "duckduckgo.com",
+        rules: &[r#"[data-testid="result"]:has(a[href{operator}"{value}"])"#],
+        final_line_break: true,
+    },
+    SearchEngineSpec {
+        name: "Bing",
+        prefix: GenerateTargetPlatform::BingPrefix,
+        fuzzy: GenerateTargetPlatform::BingFuzzy,
+        prefix_targets: &[CompileTarget::UBlockOrigin],
+        fuzzy_targets: &[CompileTarget::UBlockOrigin],
+        host: "www.bing.com",
+        rules: &[r#"li.b_algo:has(a[href{operator}"{value}"])"#],
+        final_line_break: true,
+    },
+    SearchEngineSpec {
+        name: "Yandex",
+        prefix: GenerateTargetPlatform::YandexPrefix,
+        fuzzy: GenerateTargetPlatform::YandexFuzzy,
+        prefix_targets: &[CompileTarget::UBlockOrigin],
+        fuzzy_targets: &[CompileTarget::UBlockOrigin],
+        host: "yandex.*",
+        rules: &[r#".serp-item:has(a[href{operator}"{value}"])"#],
+        final_line_break: true,
+    },
+];
+
+/// CSS attribute selector operator used by search block rules.
+const fn href_operator(prefix: bool) -> &'static str {
+    if prefix {
+        "^="
+    } else {
+        "*="
+    }
+}
+
+/// Literal block entries, whose values can be used in search block rules as is.
+fn literal_href_specs(list: &EntryList) -> impl Iterator<Item = &Entry> {
+    list.0.iter().filter(|x| x.match_method() == Some(MatchMethod::Literal) && !x.is_exception())
+}
+
+/// Trims whitespace around the value of domain, path, IP, and CIDR entries.
+fn trim_values(list: &mut EntryList, diagnostics: &Diagnostics) -> Result<(), SyntaxCheckError> {
+    let mut trimmed = 0;
+    for x in &mut list.0 {
+        let entry_type = x.entry_type();
+        let (Entry::Domain { domain: value, .. } | Entry::Path { path: value, .. } | Entry::Ip { ip: value, .. } | Entry::Cidr { cidr: value, .. }) = x else {
+            continue
+        };
+
+        if value.trim().len() == value.len() {
+            continue
+        }
+
+        if value.trim().is_empty() {
+            return Err(SyntaxCheckError::EmptyValue { entry_type })
+        }
+
+        *value = value.trim().to_string();
+        trimmed += 1;
+    }
+
+    diagnostics.log_level.verbose(format_args!("trimmed whitespace of {trimmed} entries"));
+
+    Ok(())
+}
+
+/// Lowercases domains and the host of paths. Path components after the host may be case-sensitive, so they are kept.
+fn lowercase_hosts(list: &mut EntryList) {
+    for x in &mut list.0 {
+        match x {
+            Entry::Domain { match_method: MatchMethod::Regex, .. } | Entry::Path { match_method: MatchMethod::Regex, .. } | Entry::Ip { .. } | Entry::Cidr { .. } | Entry::Regex { .. } | Entry::Comment { .. } => {}
+            Entry::Domain { domain, .. } => *domain = domain.to_lowercase(),
+            Entry::Path { path, .. } => {
+                let host = path_host(path);
+                *path = format!("{}{}", host.to_lowercase(), &path[host.len()..]);
+            }
+        }
+    }
+}
+
+/// Converts internationalized domains and the host of paths into punycode. ASCII hosts are kept as is.
+fn punycode_hosts(list: &mut EntryList) -> Result<(), CompileError> {
+    let to_ascii = |domain: &str| idna::domain_to_ascii(domain).map_err(|source| CompileError::InvalidIdn {
+        domain: domain.to_string(),
+        source,
+    });
+
+    for x in &mut list.0 {
+        match x {
+            Entry::Domain { match_method: MatchMethod::Literal | MatchMethod::Wildcard, domain, .. } if !domain.is_ascii() => {
+                *domain = to_ascii(domain)?;
+            }
+            Entry::Path { match_method: MatchMethod::Literal | MatchMethod::Wildcard, path, .. } => {
+                let host = path_host(path);
+                // the port is not a part of the domain
+                let domain = host.rsplit_once(':').map_or(host, |(domain, _)| domain);
+                if !domain.is_ascii() {
+                    *path = format!("{}{}", to_ascii(domain)?, &path[domain.len()..]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes a leading `www.` from literal and wildcard domain entries.
+/// Returns the number of modified entries.
+fn strip_www_prefix(list: &mut EntryList) -> usize {
+    let mut stripped = 0;
+    for x in &mut list.0 {
+        if let Entry::Domain { match_method: MatchMethod::Literal | MatchMethod::Wildcard, domain, .. } = x {
+            if let Some(rest) = domain.strip_prefix("www.").filter(|rest| !rest.is_empty()) {
+                *domain = rest.to_string();
+                stripped += 1;
+            }
+        }
+    }
+
+    stripped
+}
+
+/// Strips a single trailing dot from literal and wildcard domain entries.
+/// Returns the number of modified entries.
+fn strip_trailing_dot(list: &mut EntryList) -> usize {
+    let mut stripped = 0;
+    for x in &mut list.0 {
+        if let Entry::Domain { match_method: MatchMethod::Literal | MatchMethod::Wildcard, domain, .. } = x {
+            if let Some(rest) = domain.strip_suffix('.').filter(|rest| !rest.is_empty()) {
+                *domain = rest.to_string();
+                stripped += 1;
+            }
+        }
+    }
+
+    stripped
+}
+
+/// Removes entries which have the same type, match method, and value as a preceding entry.
+/// Entries are compared only if they have the same `exception`, `only_for`, `scope`, and `modifiers`, so that
+/// `note`, `added`, and `enabled` do not keep a duplicate. Comments are never removed.
+/// Returns the number of removed entries.
+fn dedup_entries(list: &mut EntryList) -> usize {
+    let before = list.0.len();
+    let mut seen = HashSet::new();
+    list.0.retain(|x| matches!(x, Entry::Comment { .. }) || seen.insert(dedup_key(x)));
+
+    before - list.0.len()
+}
+
+/// Properties of an entry which affect its rules, other than the value.
+type DedupCondition = (bool, Option<Vec<String>>, Option<Vec<String>>, Option<Vec<String>>);
+
+fn dedup_condition(x: &Entry) -> DedupCondition {
+    (x.is_exception(), x.only_for().map(<[String]>::to_vec), x.scope().map(<[String]>::to_vec), x.modifiers().map(<[String]>::to_vec))
+}
+
+/// Entries with the same key are duplicates.
+fn dedup_key(x: &Entry) -> (std::mem::Discriminant<Entry>, Option<MatchMethod>, String, DedupCondition) {
+    (std::mem::discriminant(x), x.match_method(), x.value().to_string(), dedup_condition(x))
+}
+
+/// Removes entries which are covered by another entry:
+///
+/// - an entry which has the same type, match method, and value as a preceding entry
+/// - if `wildcard_covers`, a literal domain or a wildcard domain matched by a wildcard domain, such as both of
+///   `example.com` and `*.sub.example.com` by `*.example.com`
+///
+/// Entries are compared only if they have the same `exception`, `only_for`, `scope`, and `modifiers`. Comments are never removed.
+/// Returns the value of each removed entry and its covering entry.
+fn semantic_dedup_entries(list: &mut EntryList, wildcard_covers: bool) -> Vec<(String, String)> {
+    // `*.example.com` -> `example.com`
+    let wildcard_base = |domain: &str| domain.strip_prefix("*.").filter(|base| !base.contains('*')).map(str::to_ascii_lowercase);
+
+    let mut wildcards = HashMap::new();
+    if wildcard_covers {
+        for x in &list.0 {
+            if let Entry::Domain { match_method: MatchMethod::Wildcard, domain, .. } = x {
+                if let Some(base) = wildcard_base(domain) {
+                    wildcards.entry((dedup_condition(x), base)).or_insert_with(|| domain.clone());
+                }
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut removed = vec![];
+    list.0.retain(|x| {
+        if matches!(x, Entry::Comment { .. }) {
+            return true
+        }
+
+        let condition = dedup_condition(x);
+        let covering = match x {
+            Entry::Domain { match_method: match_method @ (MatchMethod::Literal | MatchMethod::Wildcard), domain, .. } if !wildcards.is_empty() => {
+                let host = if *match_method == MatchMethod::Literal { Some(domain.to_ascii_lowercase()) } else { wildcard_base(domain) };
+                host.and_then(|host| {
+                    let mut bases = std::iter::successors(Some(host.as_str()), |x| x.split_once('.').map(|(_, parent)| parent));
+                    if *match_method == MatchMethod::Wildcard {
+                        // the wildcard itself is compared as an exact duplicate
+                        bases.next();
+                    }
+
+                    bases.find_map(|base| wildcards.get(&(condition.clone(), base.to_string()))).cloned()
+                })
+            }
+            _ => None,
+        }.or_else(|| {
+            let first = seen.insert(dedup_key(x));
+            (!first).then(|| x.value().to_string())
+        });
+
+        let Some(covering) = covering else {
+            return true
+        };
+        removed.push((x.value().to_string(), covering));
+
+        false
+    });
+
+    removed
+}
+
+fn apply_header_dup_policy(headers: Vec<HeaderAttribute>, policy: HeaderDupPolicy, diagnostics: &Diagnostics) -> Result<Vec<HeaderAttribute>, CompileError> {
+    let mut seen = HashSet::new();
+    match policy {
+        HeaderDupPolicy::KeepAll => Ok(headers),
+        HeaderDupPolicy::Error => {
+            if let Some(x) = headers.iter().find(|x| !seen.insert(x.key.clone())) {
+                return Err(CompileError::DuplicateHeader { key: x.key.clone() })
+            }
+
+            Ok(headers)
+        }
+        HeaderDupPolicy::Warn => Ok(headers.into_iter().filter(|x| {
+            let first = seen.insert(x.key.clone());
+            if !first {
+                diagnostics.warn(format!("skipped duplicated header '{}: {}'; the first one is kept", x.key, x.value));
+            }
+
+            first
+        }).collect()),
+        HeaderDupPolicy::LastWins => {
+            let mut headers = headers.into_iter().rev().filter(|x| seen.insert(x.key.clone())).collect::<Vec<_>>();
+            headers.reverse();
+
+            Ok(headers)
+        }
+    }
+}
+
+const fn determine_header_attribute_length(attr: &HeaderAttribute) -> usize {
+    2 + attr.key.len() + 2 + attr.value.len() + 1
+}
+
+/// `*.example.com` -> `example.com`. Used for targets whose rules match subdomains by default.
+fn strip_wildcard_subdomain(value: &str) -> &str {
+    value.strip_prefix("*.").unwrap_or(value)
+}
+
+/// Builds a network rule which matches the URL if and only if its host ends with `suffix`, without a label boundary.
+fn host_suffix_regex_rule(suffix: &str) -> String {
+    format!(r"/^[a-z][a-z0-9+.-]*:\/\/[^\/?#]*{}(?:[:\/?#]|$)/", regex::escape(suffix))
+}
+
+/// Builds a network rule which matches the URL if and only if its path starts with `prefix`, on every host.
+fn path_prefix_regex_rule(prefix: &str) -> String {
+    format!(r"/^[a-z][a-z0-9+.-]*:\/\/[^\/?#]+{}/", regex::escape(prefix).replace('/', r"\/"))
+}
+
+/// Wraps `pattern` into the `/regex/` form of uBlacklist, which is a JavaScript regex literal,
+/// so `/` in `pattern` is escaped unless it is already.
+fn slash_escaped_regex_rule(pattern: &str) -> String {
+    let mut buf = String::with_capacity(pattern.len() + 2);
+    buf.push('/');
+    let mut escaped = false;
+    for c in pattern.chars() {
+        if c == '/' && !escaped {
+            buf.push('\\');
+        }
+
+        escaped = c == '\\' && !escaped;
+        buf.push(c);
+    }
+    buf.push('/');
+
+    buf
+}
+
+/// Builds a network rule which matches the URL if and only if its host matches `pattern`,
+/// including its subdomains. Both uBlock Origin and uBlacklist accept this `/regex/` form.
+fn host_regex_rule(pattern: &str) -> String {
+    format!(r"/^[a-z][a-z0-9+.-]*:\/\/(?:[^\/?#]+\.)?(?:{pattern})(?:[:\/?#]|$)/")
+}
+
+/// Loads every input and concatenates their entries in order.
+/// See [`syntax_check`] for `format` and `log_level`.
+///
+/// # Errors
+/// Returns an error naming the input which failed to load.
+pub fn load_entries(inputs: &[PathBuf], format: Option<InputFormat>, log_level: LogLevel) -> Result<EntryList, CompileError> {
+    load_entries_with(inputs, format, false, &Diagnostics::new(log_level))
+}
+
+fn load_entries_with(inputs: &[PathBuf], format: Option<InputFormat>, trim_whitespace: bool, diagnostics: &Diagnostics) -> Result<EntryList, CompileError> {
+    let mut entries = vec![];
+    for path in inputs {
+        let list = check_input(path, format, trim_whitespace, diagnostics).map_err(|source| CompileError::Input {
+            path: path.clone(),
+            source,
+        })?;
+
+        let shared = Arc::<Path>::from(path.as_path());
+        entries.extend(list.0.into_iter().map(|mut x| {
+            if let Some(provenance) = x.provenance_mut() {
+                provenance.path = Some(Arc::clone(&shared));
+            }
+
+            x
+        }));
+    }
+
+    Ok(EntryList(entries))
+}
+
+fn check_wildcard(pattern: &str, host: &str) -> Result<(), SyntaxCheckError> {
+    let invalid = |reason| SyntaxCheckError::InvalidWildcard {
+        pattern: pattern.to_string(),
+        reason,
+    };
+
+    if pattern.contains(['?', '[', ']', '{', '}']) {
+        return Err(invalid("only '*' is supported"))
+    }
+
+    if strip_wildcard_subdomain(host).contains('*') {
+        return Err(invalid("'*' in a host is only allowed as the leading label (e.g. '*.example.com')"))
+    }
+
+    Ok(())
+}
+
+/// Checks that `host` is a syntactically valid host name. A single trailing dot is accepted.
+/// Non-ASCII letters are accepted so that internationalized domain names can be written as is.
+fn check_hostname(value: &str, host: &str) -> Result<(), SyntaxCheckError> {
+    let invalid = |reason| SyntaxCheckError::InvalidDomain {
+        value: value.to_string(),
+        reason,
+    };
+
+    let host = host.strip_suffix('.').unwrap_or(host);
+    if host.is_empty() {
+        return Err(invalid("host is empty"))
+    }
+
+    if host.len() > 253 {
+        return Err(invalid("host must not be longer than 253 characters"))
+    }
+
+    for label in host.split('.') {
+        if label.is_empty() {
+            return Err(invalid("host must not contain an empty label"))
+        }
+
+        if label.len() > 63 {
+            return Err(invalid("label must not be longer than 63 characters"))
+        }
+
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(invalid("label must not start or end with '-'"))
+        }
+
+        if !label.chars().all(|c| c.is_alphanumeric() || c == '-') {
+            return Err(invalid("label must consist of letters, digits, and '-'"))
+        }
+    }
+
+    Ok(())
+}
+
+/// `example.com:8080/path` -> `example.com:8080`
+fn path_host(path: &str) -> &str {
+    path.split_once('/').map_or(path, |(host, _)| host)
+}
+
+/// Checks the host component of a path, which may be followed by a port.
+fn check_path_host(path: &str, host: &str) -> Result<(), SyntaxCheckError> {
+    let invalid = |reason| SyntaxCheckError::InvalidPath {
+        value: path.to_string(),
+        reason,
+    };
+
+    if host.is_empty() {
+        return Err(invalid("must start with a host, such as 'example.com/path'"))
+    }
+
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) => {
+            if port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid("port must consist of digits"))
+            }
+
+            host
+        }
+        None => host,
+    };
+
+    check_hostname(path, host).map_err(|e| match e {
+        SyntaxCheckError::InvalidDomain { reason, .. } => invalid(reason),
+        e => e,
+    })
+}
+
+/// Network filter options of uBlock Origin, without the value after `=`.
+/// `domain` is not included, since it is expressed with the scope.
+const UBLOCK_MODIFIERS: &[&str] = &[
+    "1p", "first-party", "3p", "third-party", "strict1p", "strict3p", "all", "badfilter", "important", "match-case",
+    "document", "doc", "script", "stylesheet", "css", "image", "media", "font", "object", "xmlhttprequest", "xhr",
+    "websocket", "ping", "subdocument", "frame", "popup", "popunder", "other", "inline-script", "inline-font",
+    "csp", "denyallow", "from", "to", "header", "method", "permissions", "redirect", "redirect-rule", "removeparam",
+    "replace", "urlskip", "uritransform", "ipaddress", "cname", "empty", "mp4", "ehide", "elemhide", "shide",
+    "specifichide", "ghide", "generichide", "genericblock",
+];
+
+fn check_modifier(modifier: &str) -> Result<(), SyntaxCheckError> {
+    let invalid = |reason| SyntaxCheckError::InvalidModifier {
+        modifier: modifier.to_string(),
+        reason,
+    };
+
+    if modifier.contains([',', '$']) || modifier.contains(char::is_whitespace) {
+        return Err(invalid("must not contain ',', '$', or whitespace"))
+    }
+
+    let name = modifier.strip_prefix('~').unwrap_or(modifier);
+    let name = name.split_once('=').map_or(name, |(name, _)| name);
+    if !UBLOCK_MODIFIERS.contains(&name) {
+        return Err(invalid("not a known uBlock Origin modifier"))
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_entry(entry: &Entry) -> Result<(), SyntaxCheckError> {
+    if let Some(note) = entry.note().filter(|note| note.contains(['\n', '\r'])) {
+        return Err(SyntaxCheckError::InvalidNote {
+            note: note.to_string(),
+        })
+    }
+
+    if let Some(added) = entry.added() {
+        added.parse::<IsoDate>().map_err(|e| SyntaxCheckError::InvalidDate {
+            value: e.input,
+            reason: e.reason,
+        })?;
+    }
+
+    for domain in entry.scope().into_iter().flatten() {
+        check_hostname(domain, domain)?;
+    }
+
+    for modifier in entry.modifiers().into_iter().flatten() {
+        check_modifier(modifier)?;
+    }
+
+    if let Some(flag) = entry.only_for().into_iter().flatten().find(|x| x.parse::<GenerateTargetPlatform>().is_err()) {
+        return Err(SyntaxCheckError::InvalidFeatureFlag {
+            flag: flag.clone(),
+        })
+    }
+
+    // a literal `|` or `^` would anchor the rule of uBlock Origin and similar targets, so it is rejected for every target
+    if matches!(entry, Entry::Domain { .. } | Entry::Path { .. }) && entry.match_method() != Some(MatchMethod::Regex) {
+        if let Some((character, encoded)) = entry.value().chars().find_map(|x| match x {
+            '|' => Some((x, "%7C")),
+            '^' => Some((x, "%5E")),
+            _ => None,
+        }) {
+            return Err(SyntaxCheckError::AnchorCharacter {
+                entry_type: entry.entry_type(),
+                value: entry.value().to_string(),
+                character,
+                encoded,
+            })
+        }
+    }
+
+    match entry {
+        Entry::Domain { match_method, domain, .. } => match *match_method {
+            MatchMethod::Literal => check_hostname(domain, domain),
+            MatchMethod::Regex => check_regex(domain),
+            MatchMethod::Wildcard => {
+                check_wildcard(domain, domain)?;
+                check_hostname(domain, strip_wildcard_subdomain(domain))
+            }
+            MatchMethod::Substring => check_substring(domain),
+            MatchMethod::Suffix => check_suffix(domain),
+            MatchMethod::Prefix => Err(SyntaxCheckError::InvalidPrefix {
+                value: domain.clone(),
+                reason: "only path entries support 'prefix'",
+            }),
+        },
+        // the scheme is added by each target, such as `*://` of uBlacklist
+        Entry::Path { match_method, path, .. } if *match_method != MatchMethod::Regex && path.contains("://") => Err(SyntaxCheckError::PathWithScheme {
+            value: path.clone(),
+            suggestion: path.split_once("://").map_or_else(String::new, |(_, rest)| rest.to_string()),
+        }),
+        Entry::Path { match_method, path, .. } => match *match_method {
+            MatchMethod::Literal => check_path_host(path, path_host(path)),
+            MatchMethod::Regex => check_regex(path),
+            MatchMethod::Wildcard => {
+                let host = path_host(path);
+                check_wildcard(path, host)?;
+                check_path_host(path, strip_wildcard_subdomain(host))
+            }
+            MatchMethod::Substring => check_substring(path),
+            MatchMethod::Suffix => check_suffix(path),
+            MatchMethod::Prefix => check_prefix(path),
+        },
+        Entry::Ip { match_method, ip, .. } => match *match_method {
+            MatchMethod::Literal => check_ip(ip),
+            MatchMethod::Regex => check_regex(ip),
+            MatchMethod::Wildcard | MatchMethod::Substring | MatchMethod::Suffix | MatchMethod::Prefix => Err(SyntaxCheckError::InvalidIp {
+                value: ip.clone(),
+                reason: "only 'literal' and 'regex' are supported",
+            }),
+        },
+        Entry::Cidr { cidr, .. } => check_cidr(cidr),
+        Entry::Regex { pattern, .. } if pattern.is_empty() => Err(SyntaxCheckError::EmptyPattern),
+        Entry::Regex { pattern, .. } => check_regex(pattern),
+        // a line break would start a rule
+        Entry::Comment { text } if text.contains(['\n', '\r']) => Err(SyntaxCheckError::InvalidComment {
+            text: text.clone(),
+        }),
+        Entry::Comment { .. } => Ok(()),
+    }
+}
+
+/// `192.0.2.0/24` -> (`192.0.2.0`, `24`). `None` if it is not a network address with a valid prefix length.
+fn parse_cidr(cidr: &str) -> Option<(IpAddr, u8)> {
+    let (network, prefix) = cidr.split_once('/')?;
+    let network = network.parse::<IpAddr>().ok()?;
+    let prefix = prefix.parse::<u8>().ok()?;
+    let host_bits_zero = match network {
+        IpAddr::V4(x) => prefix <= 32 && u32::from(x).checked_shl(u32::from(prefix)).unwrap_or(0) == 0,
+        IpAddr::V6(x) => prefix <= 128 && u128::from(x).checked_shl(u32::from(prefix)).unwrap_or(0) == 0,
+    };
+
+    host_bits_zero.then_some((network, prefix))
+}
+
+/// Replaces every `${VAR}` in the value of `attribute` with the environment variable `VAR`.
+fn expand_env_vars(attribute: HeaderAttribute) -> Result<HeaderAttribute, CompileError> {
+    let invalid = |reason| CompileError::HeaderExpansion {
+        key: attribute.key.clone(),
+        reason,
+    };
+
+    let mut value = String::with_capacity(attribute.value.len());
+    let mut rest = attribute.value.as_str();
+    while let Some((before, after)) = rest.split_once("${") {
+        value.push_str(before);
+        let Some((name, after)) = after.split_once('}') else {
+            return Err(invalid("'${' is not closed with '}'".to_string()))
+        };
+
+        match std::env::var(name) {
+            Ok(x) => value.push_str(&x),
+            Err(VarError::NotPresent) => return Err(invalid(format!("environment variable '{name}' is not defined"))),
+            Err(VarError::NotUnicode(_)) => return Err(invalid(format!("environment variable '{name}' is not valid UTF-8"))),
+        }
+        rest = after;
+    }
+    value.push_str(rest);
+
+    Ok(HeaderAttribute {
+        key: attribute.key,
+        value,
+    })
+}
+
+fn check_template(template: &str) -> Result<(), CompileError> {
+    let invalid = |reason| CompileError::InvalidTemplate {
+        template: template.to_string(),
+        reason,
+    };
+
+    if !template.contains("{value}") {
+        return Err(invalid("must contain '{value}'"))
+    }
+
+    if template.contains(['\n', '\r']) {
+        return Err(invalid("must not contain a line break"))
+    }
+
+    Ok(())
+}
+
+fn check_cidr(cidr: &str) -> Result<(), SyntaxCheckError> {
+    parse_cidr(cidr).ok_or_else(|| SyntaxCheckError::InvalidCidr {
+        value: cidr.to_string(),
+        reason: "expected a network address and a prefix length, such as '192.0.2.0/24'",
+    })?;
+
+    Ok(())
+}
+
+fn check_ip(ip: &str) -> Result<(), SyntaxCheckError> {
+    ip.parse::<IpAddr>().map_err(|_| SyntaxCheckError::InvalidIp {
+        value: ip.to_string(),
+        reason: "not an IPv4 or IPv6 address",
+    })?;
+
+    Ok(())
+}
+
+fn check_substring(value: &str) -> Result<(), SyntaxCheckError> {
+    check_unanchored(value).map_err(|reason| SyntaxCheckError::InvalidSubstring {
+        value: value.to_string(),
+        reason,
+    })
+}
+
+fn check_suffix(value: &str) -> Result<(), SyntaxCheckError> {
+    check_unanchored(value).map_err(|reason| SyntaxCheckError::InvalidSuffix {
+        value: value.to_string(),
+        reason,
+    })
+}
+
+fn check_prefix(path: &str) -> Result<(), SyntaxCheckError> {
+    check_unanchored(path).map_err(|reason| SyntaxCheckError::InvalidPrefix {
+        value: path.to_string(),
+        reason,
+    })?;
+
+    if path.starts_with('/') {
+        Ok(())
+    } else {
+        check_path_host(path, path_host(path))
+    }
+}
+
+/// Checks a value which is written into a rule without its own anchors.
+fn check_unanchored(value: &str) -> Result<(), &'static str> {
+    if value.is_empty() {
+        return Err("must not be empty")
+    }
+
+    if value.contains(char::is_whitespace) {
+        return Err("must not contain whitespace")
+    }
+
+    if value.contains(['*', '^', '|']) {
+        return Err("must not contain '*', '^', or '|'")
+    }
+
+    Ok(())
+}
+
+fn check_regex(pattern: &str) -> Result<(), SyntaxCheckError> {
+    regex::Regex::new(pattern).map_err(|source| SyntaxCheckError::InvalidRegex {
+        pattern: pattern.to_string(),
+        source,
+    })?;
+
+    Ok(())
+}
+
+/// [`MatchMethod::Substring`] values shorter than this are warned by [`syntax_check`].
+const SHORT_SUBSTRING_LENGTH: usize = 4;
+
+/// Loads `input` and validates its entries. `-` means stdin.
+/// If `format` is `None`, it is detected by [`InputFormat::detect`]. Warnings are printed according to `log_level`.
+///
+/// Unlike [`compile`], this also rejects a value which is both blocked and allowed by exception entries.
+///
+/// # Errors
+/// Returns an error if `input` can not be read, is not a valid entry list, or contains an invalid entry.
+pub fn syntax_check(input: &Path, format: Option<InputFormat>, log_level: LogLevel) -> Result<EntryList, SyntaxCheckError> {
+    let list = check_input(input, format, false, &Diagnostics::new(log_level))?;
+    check_conflicts(&list)?;
+
+    Ok(list)
+}
+
+/// Rejects block entries which have the same type, match method, and value as an exception entry.
+/// Every conflict is reported at once.
+fn check_conflicts(list: &EntryList) -> Result<(), SyntaxCheckError> {
+    let key = |x: &Entry| (x.entry_type(), x.match_method(), x.value().to_string());
+    let allowed = list.0.iter().filter(|x| x.is_exception()).map(key).collect::<HashSet<_>>();
+
+    let mut seen = HashSet::new();
+    let conflicts = list.0.iter()
+        .filter(|x| !x.is_exception() && !matches!(x, Entry::Comment { .. }))
+        .filter(|x| {
+            let key = key(x);
+            allowed.contains(&key) && seen.insert(key)
+        })
+        .map(|x| format!("{} '{}'", x.entry_type(), x.value()))
+        .collect::<Vec<_>>();
+
+    if !conflicts.is_empty() {
+        return Err(SyntaxCheckError::ConflictingEntries { conflicts })
+    }
+
+    Ok(())
+}
+
+fn check_input(input: &Path, format: Option<InputFormat>, trim_whitespace: bool, diagnostics: &Diagnostics) -> Result<EntryList, SyntaxCheckError> {
+    let format = format.unwrap_or_else(|| InputFormat::detect(input));
+    let mut x = if format == InputFormat::Ndjson {
+        // read line by line, so that the whole input is never held in memory
+        parse_ndjson(open_input(input)?)?
+    } else {
+        parse_text(&read_input(input)?, format)?
+    };
+
+    if trim_whitespace {
+        trim_values(&mut x, diagnostics)?;
+    }
+    check_entries(&x, diagnostics)?;
+
+    Ok(x)
+}
+
+/// Parses `text` as `format`, without validating the entries.
+fn parse_text(text: &str, format: InputFormat) -> Result<EntryList, SyntaxCheckError> {
+    Ok(match format {
+        InputFormat::Json => serde_json::from_str(text).map_err(|source| SyntaxCheckError::Deserialize {
+            snippet: json_error_snippet(text, &source),
+            source,
+        })?,
+        // positions in the error are the same as in the original text
+        InputFormat::Jsonc => serde_json::from_str(&strip_json_comments(text)).map_err(|source| SyntaxCheckError::Deserialize {
+            snippet: json_error_snippet(text, &source),
+            source,
+        })?,
+        InputFormat::Yaml => serde_norway::from_str(text)?,
+        InputFormat::Toml => EntryList(toml::from_str::<TomlEntryList>(text)?.entry.into_iter().enumerate().flat_map(|(i, x)| x.at(Position::Index(i + 1)).expand()).collect()),
+        InputFormat::Lines => parse_lines(text),
+        InputFormat::Ndjson => parse_ndjson(text.as_bytes())?,
+    })
+}
+
+fn check_entries(list: &EntryList, diagnostics: &Diagnostics) -> Result<(), SyntaxCheckError> {
+    for entry in &list.0 {
+        check_entry(entry).map_err(|source| match entry.provenance().and_then(|x| x.position) {
+            Some(position) => SyntaxCheckError::Positioned {
+                position,
+                source: Box::new(source),
+            },
+            None => source,
+        })?;
+
+        if let Some(warning) = entry_warning(entry) {
+            diagnostics.warn(warning);
+        }
+    }
+
+    Ok(())
+}
+
+/// Warning about a valid entry which may not work as intended.
+fn entry_warning(entry: &Entry) -> Option<String> {
+    (entry.match_method() == Some(MatchMethod::Substring) && entry.value().chars().count() < SHORT_SUBSTRING_LENGTH)
+        .then(|| format!("substring '{}' is shorter than {SHORT_SUBSTRING_LENGTH} characters; it may match unexpectedly", entry.value()))
+}
+
+#[derive(Serialize, Copy, Clone, Eq, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found by [`syntax_check_report`].
+#[derive(Serialize, Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// 1-based. Known for a malformed input and for entries of NDJSON and lines inputs.
+    pub line: Option<usize>,
+    /// 1-based. Known for a malformed input.
+    pub column: Option<usize>,
+    /// 1-based index of the entry in a JSON, YAML, or TOML input.
+    pub entry: Option<usize>,
+}
+
+impl Diagnostic {
+    const fn at(severity: Severity, message: String, position: Option<Position>) -> Self {
+        Self {
+            severity,
+            message,
+            line: match position {
+                Some(Position::Line(x)) => Some(x),
+                _ => None,
+            },
+            column: None,
+            entry: match position {
+                Some(Position::Index(x)) => Some(x),
+                _ => None,
+            },
+        }
+    }
+
+    const fn malformed(message: String, line: usize, column: usize) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+            line: Some(line),
+            column: Some(column),
+            entry: None,
+        }
+    }
+}
+
+/// Like [`syntax_check`], but reports every problem of `input` instead of failing on the first one.
+///
+/// Each entry is deserialized and validated on its own, and duplicated entries are warned.
+/// Only a malformed document, such as an unclosed array, hides the problems of its entries.
+///
+/// # Errors
+/// Returns an error only if `input` can not be read.
+pub fn syntax_check_report(input: &Path, format: Option<InputFormat>) -> Result<Vec<Diagnostic>, SyntaxCheckError> {
+    let (entries, mut diagnostics) = parse_entries_separately(input, format.unwrap_or_else(|| InputFormat::detect(input)))?;
+    let position = |x: &Entry| x.provenance().and_then(|x| x.position);
+
+    let allowed = entries.iter().filter(|x| x.is_exception()).map(|x| (x.entry_type(), x.match_method(), x.value())).collect::<HashSet<_>>();
+    let mut seen = HashMap::<&Entry, Option<Position>>::new();
+    for entry in &entries {
+        if let Err(e) = check_entry(entry) {
+            diagnostics.push(Diagnostic::at(Severity::Error, e.to_string(), position(entry)));
+            continue
+        }
+
+        if let Some(warning) = entry_warning(entry) {
+            diagnostics.push(Diagnostic::at(Severity::Warning, warning, position(entry)));
+        }
+
+        if matches!(entry, Entry::Comment { .. }) {
+            continue
+        }
+
+        if !entry.is_exception() && allowed.contains(&(entry.entry_type(), entry.match_method(), entry.value())) {
+            diagnostics.push(Diagnostic::at(Severity::Error, format!("{} '{}' is both blocked and allowed", entry.entry_type(), entry.value()), position(entry)));
+        }
+
+        match seen.get(entry) {
+            Some(first) => {
+                let first = first.map_or_else(String::new, |x| format!(" (first at {x})"));
+                diagnostics.push(Diagnostic::at(Severity::Warning, format!("duplicated {} '{}'{first}", entry.entry_type(), entry.value()), position(entry)));
+            }
+            None => {
+                seen.insert(entry, position(entry));
+            }
+        }
+    }
+
+    // stable, so that problems of the same entry keep their order
+    diagnostics.sort_by_key(|x| x.line.or(x.entry));
+
+    Ok(diagnostics)
+}
+
+/// The message of `error` without ` at line L column C`, which is reported separately.
+fn json_error_message(error: &serde_json::Error) -> String {
+    let message = error.to_string();
+    let position = format!(" at line {} column {}", error.line(), error.column());
+
+    message.strip_suffix(&position).map_or_else(|| message.clone(), str::to_string)
+}
+
+/// Deserializes each entry of `input` on its own. An entry which can not be deserialized is reported and skipped.
+fn parse_entries_separately(input: &Path, format: InputFormat) -> Result<(Vec<Entry>, Vec<Diagnostic>), SyntaxCheckError> {
+    let mut entries = vec![];
+    let mut diagnostics = vec![];
+    let mut push = |i: usize, x: Result<Entry<MatchMethods>, String>| match x {
+        Ok(x) => entries.extend(x.at(Position::Index(i + 1)).expand()),
+        Err(message) => diagnostics.push(Diagnostic::at(Severity::Error, message, Some(Position::Index(i + 1)))),
+    };
+
+    if format == InputFormat::Ndjson {
+        for x in read_ndjson_entries(open_input(input)?) {
+            match x {
+                Ok(x) => entries.push(x),
+                Err(SyntaxCheckError::DeserializeLine { line_number, source }) => diagnostics.push(Diagnostic::malformed(json_error_message(&source), line_number, source.column())),
+                Err(e) => return Err(e),
+            }
+        }
+
+        return Ok((entries, diagnostics))
+    }
+
+    let text = read_input(input)?;
+    match format {
+        InputFormat::Json | InputFormat::Jsonc => {
+            let text = if format == InputFormat::Jsonc { strip_json_comments(&text) } else { text };
+            match serde_json::from_str::<Vec<serde_json::Value>>(&text) {
+                Ok(values) => {
+                    for (i, x) in values.into_iter().enumerate() {
+                        push(i, serde_json::from_value(x).map_err(|e| e.to_string()));
+                    }
+                }
+                Err(e) => diagnostics.push(Diagnostic::malformed(json_error_message(&e), e.line(), e.column())),
+            }
+        }
+        InputFormat::Yaml => match serde_norway::from_str::<Vec<serde_norway::Value>>(&text) {
+            Ok(values) => {
+                for (i, x) in values.into_iter().enumerate() {
+                    push(i, serde_norway::from_value(x).map_err(|e| e.to_string()));
+                }
+            }
+            Err(e) => {
+                let (line, column) = e.location().map_or((1, 1), |x| (x.line(), x.column()));
+                diagnostics.push(Diagnostic::malformed(e.to_string(), line, column));
+            }
+        },
+        InputFormat::Toml => {
+            #[derive(Deserialize)]
+            #[serde(deny_unknown_fields)]
+            struct TomlValues {
+                #[serde(default)]
+                entry: Vec<toml::Value>,
+            }
+
+            match toml::from_str::<TomlValues>(&text) {
+                Ok(values) => {
+                    for (i, x) in values.entry.into_iter().enumerate() {
+                        push(i, x.try_into().map_err(|e: toml::de::Error| e.message().to_string()));
+                    }
+                }
+                Err(e) => {
+                    let (line, column) = e.span().map_or((1, 1), |x| line_column(&text, x.start));
+                    diagnostics.push(Diagnostic::malformed(e.message().to_string(), line, column));
+                }
+            }
+        }
+        InputFormat::Lines => entries = parse_lines(&text).0,
+        InputFormat::Ndjson => unreachable!("parsed above"),
+    }
+
+    Ok((entries, diagnostics))
+}
+
+/// 1-based line and column (in characters) of the byte `offset` of `text`.
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+/// Reads one JSON entry per line. Blank lines are skipped.
+fn parse_ndjson(reader: impl BufRead) -> Result<EntryList, SyntaxCheckError> {
+    read_ndjson_entries(reader).collect::<Result<_, _>>().map(EntryList)
+}
+
+/// Reads NDJSON entries from `reader` one line at a time, so that a large input is never held in memory.
+///
+/// Blank lines are skipped, and an entry with an array of match methods yields one entry for each method.
+/// Entries are not validated. Pass them to [`compile_entries`], which validates them as if loaded from a file.
+/// A line which can not be read or deserialized yields an error, and reading continues with the next line.
+pub fn read_ndjson_entries<R: BufRead>(reader: R) -> impl Iterator<Item = Result<Entry, SyntaxCheckError>> {
+    reader.lines().enumerate().flat_map(|(i, line)| {
+        let entries = line.map_err(SyntaxCheckError::from).and_then(|line| {
+            if line.trim().is_empty() {
+                return Ok(vec![])
+            }
+
+            serde_json::from_str::<Entry<MatchMethods>>(&line).map(|x| x.at(Position::Line(i + 1)).expand()).map_err(|source| SyntaxCheckError::DeserializeLine {
+                line_number: i + 1,
+                source,
+            })
+        });
+
+        match entries {
+            Ok(entries) => entries.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        }
+    })
+}
+
+/// Reads one literal domain per line. Blank lines and comments are skipped.
+fn parse_lines(text: &str) -> EntryList {
+    EntryList(text.lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with(['#', '!']))
+        .map(|(i, line)| Entry::Domain {
+            match_method: MatchMethod::Literal,
+            domain: line.to_string(),
+            attributes: EntryAttributes {
+                provenance: Provenance {
+                    path: None,
+                    position: Some(Position::Line(i + 1)),
+                },
+                ..EntryAttributes::default()
+            },
+        })
+        .collect())
+}
+
+/// Replaces `// line` and `/* block */` comments outside of strings with spaces.
+/// Line breaks and byte offsets are kept, so that error positions still point into the original text.
+fn strip_json_comments(text: &str) -> String {
+    enum State {
+        Normal,
+        String { escaped: bool },
+        LineComment,
+        BlockComment,
+    }
+
+    let blank = |buf: &mut String, c: char| {
+        if c == '\n' {
+            buf.push('\n');
+        } else {
+            buf.extend(std::iter::repeat_n(' ', c.len_utf8()));
+        }
+    };
+
+    let mut buf = String::with_capacity(text.len());
+    let mut state = State::Normal;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        state = match state {
+            State::Normal => match (c, chars.peek()) {
+                ('/', Some('/')) => {
+                    chars.next();
+                    buf.push_str("  ");
+                    State::LineComment
+                }
+                ('/', Some('*')) => {
+                    chars.next();
+                    buf.push_str("  ");
+                    State::BlockComment
+                }
+                _ => {
+                    buf.push(c);
+                    if c == '"' { State::String { escaped: false } } else { State::Normal }
+                }
+            },
+            State::String { escaped } => {
+                buf.push(c);
+                match c {
+                    '"' if !escaped => State::Normal,
+                    '\\' => State::String { escaped: !escaped },
+                    _ => State::String { escaped: false },
+                }
+            }
+            State::LineComment => {
+                blank(&mut buf, c);
+                if c == '\n' { State::Normal } else { State::LineComment }
+            }
+            State::BlockComment => {
+                if c == '*' && chars.peek() == Some(&'/') {
+                    chars.next();
+                    buf.push_str("  ");
+                    State::Normal
+                } else {
+                    blank(&mut buf, c);
+                    State::BlockComment
+                }
+            }
+        };
+    }
+
+    buf
+}
+
+/// Returns a few characters of `text` around the position of `error`.
+fn json_error_snippet(text: &str, error: &serde_json::Error) -> String {
+    const CONTEXT: usize = 20;
+
+    let Some(line) = text.lines().nth(error.line().saturating_sub(1)) else {
+        return String::new()
+    };
+
+    // column is 1-based, and counted in bytes
+    let column = error.column().saturating_sub(1).min(line.len());
+    let mut start = column.saturating_sub(CONTEXT);
+    while !line.is_char_boundary(start) {
+        start -= 1;
+    }
+
+    let mut end = (column + CONTEXT).min(line.len());
+    while !line.is_char_boundary(end) {
+        end += 1;
+    }
+
+    line[start..end].to_string()
+}
+
+/// Reads a prepended or appended file. A line break is added to the end if missing.
+fn read_auxiliary_file(path: &Path) -> Result<String, CompileError> {
+    let mut text = std::fs::read_to_string(path).map_err(|source| CompileError::AuxiliaryFile {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+
+    Ok(text)
+}
+
+/// Reads header attributes, one `K=V` per line. Blank lines and lines starting with `#` are skipped.
+fn read_header_file(path: &Path) -> Result<Vec<HeaderAttribute>, CompileError> {
+    let text = std::fs::read_to_string(path).map_err(|source| CompileError::AuxiliaryFile {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| line.parse().map_err(|source| CompileError::InvalidHeaderFile {
+            path: path.to_path_buf(),
+            line_number: i + 1,
+            source,
+        }))
+        .collect()
+}
+
+/// Reads the whole `input`. `-` means stdin.
+pub(crate) fn read_input(input: &Path) -> std::io::Result<String> {
+    let mut text = String::new();
+    open_input(input)?.read_to_string(&mut text)?;
+
+    Ok(text)
+}
+
+/// Opens `input` for reading. `-` means stdin.
+fn open_input(input: &Path) -> std::io::Result<Box<dyn BufRead>> {
+    if input.as_os_str() == "-" {
+        Ok(Box::new(BufReader::new(std::io::stdin())))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(input)?)))
+    }
+}
+
+/// Opens `output` for writing, truncating it. `-` means stdout.
+pub(crate) fn open_output(output: &Path) -> std::io::Result<Box<dyn Write>> {
+    if output.as_os_str() == "-" {
+        Ok(Box::new(BufWriter::new(std::io::stdout())))
+    } else {
+        Ok(Box::new(BufWriter::new(
+            File::options().write(true).truncate(true).create(true).open(output)?
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(json: &str) -> EntryList {
+        serde_json::from_str(json).expect("fixture must be an entry list")
+    }
+
+    fn compile(target: CompileTarget, json: &str) -> String {
+        compile_to_string(&list(json), target, &[GenerateTargetPlatform::Base], &[]).expect("fixture must compile")
+    }
+
+    fn compile_with(options: &CompileOptions, json: &str) -> Result<String, CompileError> {
+        let mut buf = vec![];
+        compile_entries(list(json).0, &mut buf, options)?;
+
+        Ok(String::from_utf8(buf).expect("output must be UTF-8"))
+    }
+
+    /// Whether the `/regex/` rule matches `url`. `\/` of the rule is also valid in Rust.
+    fn regex_rule_matches(rule: &str, url: &str) -> bool {
+        let pattern = rule.strip_prefix('/').and_then(|x| x.strip_suffix('/')).expect("rule must be a regex rule");
+
+        regex::Regex::new(pattern).expect("rule must be a valid regex").is_match(url)
+    }
+
+    #[test]
+    fn regex_domain_matches_host_and_subdomains() {
+        let rule = host_regex_rule(r"ads-[0-9]+\.example\.com");
+
+        assert!(regex_rule_matches(&rule, "https://ads-1.example.com/"));
+        assert!(regex_rule_matches(&rule, "https://www.ads-23.example.com:8080/banner"));
+        assert!(!regex_rule_matches(&rule, "https://ads-x.example.com/"));
+        assert!(!regex_rule_matches(&rule, "https://example.com/?ads-1.example.com"));
+        assert_eq!(compile(CompileTarget::UBlockOrigin, r#"[{"type": "domain", "match": "regex", "domain": "ads-[0-9]+\\.example\\.com"}]"#), format!("{rule}\n"));
+    }
+
+    #[test]
+    fn invalid_regex_is_rejected() {
+        let entry = list(r#"[{"type": "domain", "match": "regex", "domain": "ads-[0-9"}]"#).0.remove(0);
+
+        assert!(matches!(check_entry(&entry), Err(SyntaxCheckError::InvalidRegex { .. })));
+    }
+
+    #[test]
+    fn comment_with_line_break_is_rejected() {
+        for text in ["hello\n||evil.com^", "hello\r||evil.com^"] {
+            let entry = Entry::Comment { text: text.to_string() };
+
+            assert!(matches!(check_entry(&entry), Err(SyntaxCheckError::InvalidComment { .. })), "{text:?}");
+        }
+        assert!(check_entry(&Entry::Comment { text: "hello".to_string() }).is_ok());
+    }
+
+    /// A comment, a literal, an exception, a wildcard, a path, and an IP entry.
+    const MIXED_ENTRIES: &str = r#"[
+        {"type": "comment", "text": "ads"},
+        {"type": "domain", "match": "literal", "domain": "a.com"},
+        {"type": "domain", "match": "literal", "domain": "b.com", "exception": true},
+        {"type": "domain", "match": "wildcard", "domain": "*.c.com"},
+        {"type": "path", "match": "literal", "path": "d.com/ad"},
+        {"type": "ip", "match": "literal", "ip": "192.0.2.1"}
+    ]"#;
+
+    #[test]
+    fn hosts_emits_only_literal_block_domains() {
+        assert_eq!(compile(CompileTarget::Hosts, MIXED_ENTRIES), "# ads\n0.0.0.0 a.com\n");
+    }
+
+    #[test]
+    fn adguard_appends_modifiers_to_every_rule() {
+        let options = CompileOptions {
+            adguard_modifiers: vec![AdGuardModifier::Important, AdGuardModifier::DnsRewrite("0.0.0.0".to_string())],
+            ..CompileOptions::new(CompileTarget::AdGuard, vec![GenerateTargetPlatform::Base])
+        };
+
+        assert_eq!(compile(CompileTarget::AdGuard, MIXED_ENTRIES), "! ads\n||a.com^\n@@||b.com^\n||c.com^\n||192.0.2.1^\n");
+        assert_eq!(
+            compile_with(&options, MIXED_ENTRIES).expect("fixture must compile"),
+            "! ads\n||a.com^$important,dnsrewrite=0.0.0.0\n@@||b.com^$important,dnsrewrite=0.0.0.0\n||c.com^$important,dnsrewrite=0.0.0.0\n||192.0.2.1^$important,dnsrewrite=0.0.0.0\n",
+        );
+    }
+
+    #[test]
+    fn adguard_modifiers_are_unsupported_for_other_targets() {
+        let options = CompileOptions {
+            adguard_modifiers: vec![AdGuardModifier::Important],
+            ..CompileOptions::new(CompileTarget::UBlockOrigin, vec![GenerateTargetPlatform::Base])
+        };
+
+        assert!(matches!(compile_with(&options, MIXED_ENTRIES), Err(CompileError::UnsupportedFeatureSet)));
+    }
+
+    #[test]
+    fn dnsmasq_emits_block_domains_with_subdomains() {
+        // `address=/c.com/` also covers every subdomain, as `*.c.com` does
+        assert_eq!(compile(CompileTarget::Dnsmasq, MIXED_ENTRIES), "# ads\naddress=/a.com/0.0.0.0\naddress=/c.com/0.0.0.0\n");
+    }
+
+    #[test]
+    fn wildcard_is_translated_per_target() {
+        let json = r#"[
+            {"type": "domain", "match": "wildcard", "domain": "*.doubleclick.net"},
+            {"type": "path", "match": "wildcard", "path": "b.com/*/ad"}
+        ]"#;
+
+        assert_eq!(compile(CompileTarget::UBlockOrigin, json), "||doubleclick.net^\n||b.com/*/ad^\n");
+        assert_eq!(compile(CompileTarget::UBlackList, json), "*://*.doubleclick.net/*\n*://b.com/*/ad\n");
+    }
+
+    #[test]
+    fn wildcard_is_only_allowed_as_the_leading_label() {
+        assert!(check_wildcard("*.example.com", "*.example.com").is_ok());
+        assert!(matches!(check_wildcard("ads.*.com", "ads.*.com"), Err(SyntaxCheckError::InvalidWildcard { .. })));
+        assert!(matches!(check_wildcard("ads?.com", "ads?.com"), Err(SyntaxCheckError::InvalidWildcard { .. })));
+    }
+
+    #[test]
+    fn yaml_is_parsed_as_json_is() {
+        let yaml = "
+# comments are allowed
+- type: domain
+  match: [literal, wildcard]
+  domain: a.com
+  note: ads
+- type: path
+  match: literal
+  path: b.com/ad
+";
+        let json = r#"[
+            {"type": "domain", "match": "literal", "domain": "a.com", "note": "ads"},
+            {"type": "domain", "match": "wildcard", "domain": "a.com", "note": "ads"},
+            {"type": "path", "match": "literal", "path": "b.com/ad"}
+        ]"#;
+
+        assert_eq!(parse_text(yaml, InputFormat::Yaml).expect("YAML must be parsed").0, list(json).0);
+    }
+
+    #[test]
+    fn yaml_is_detected_from_the_extension() {
+        assert_eq!(InputFormat::detect(Path::new("list.yaml")), InputFormat::Yaml);
+        assert_eq!(InputFormat::detect(Path::new("list.yml")), InputFormat::Yaml);
+        assert_eq!(InputFormat::detect(Path::new("-")), InputFormat::Json);
+    }
+
+    #[test]
+    fn invalid_yaml_reports_its_location() {
+        let error = parse_text("- type: domain\n  match: literal\n  domain: [a.com\n", InputFormat::Yaml).expect_err("YAML must be invalid");
+
+        let SyntaxCheckError::Yaml(error) = error else {
+            panic!("unexpected error: {error}")
+        };
+        assert!(error.location().is_some());
+    }
+
+    #[test]
+    fn toml_entries_are_an_array_of_tables() {
+        let toml = r#"
+[[entry]]
+type = "comment"
+text = "ads"
+
+[[entry]]
+type = "domain"
+match = ["literal", "wildcard"]
+domain = "a.com"
+"#;
+        let list = parse_text(toml, InputFormat::Toml).expect("TOML must be parsed");
+
+        assert_eq!(list.0.len(), 3);
+        assert_eq!(list.0[1].match_method(), Some(MatchMethod::Literal));
+        assert_eq!(list.0[2].match_method(), Some(MatchMethod::Wildcard));
+        // both match methods come from the second table
+        assert!(list.0[1..].iter().all(|x| matches!(x.provenance().and_then(|x| x.position), Some(Position::Index(2)))));
+    }
+
+    #[test]
+    fn toml_rejects_unknown_tables() {
+        let toml = "[[entries]]\ntype = \"domain\"\nmatch = \"literal\"\ndomain = \"a.com\"\n";
+
+        assert!(matches!(parse_text(toml, InputFormat::Toml), Err(SyntaxCheckError::Toml(_))));
+    }
+
+    /// Kept headers and the number of warnings.
+    fn headers(policy: HeaderDupPolicy) -> Result<(Vec<String>, usize), CompileError> {
+        let headers = ["Title=A", "Expires=1 day", "Title=B"].map(|x| x.parse::<HeaderAttribute>().expect("header must be valid")).to_vec();
+        let diagnostics = Diagnostics::new(LogLevel::Quiet);
+        let headers = apply_header_dup_policy(headers, policy, &diagnostics)?.into_iter().map(|x| format!("{}={}", x.key, x.value)).collect();
+
+        Ok((headers, diagnostics.into_warnings().len()))
+    }
+
+    #[test]
+    fn header_dup_policy_keeps_the_chosen_occurrence() {
+        let kept = |policy| headers(policy).expect("policy must not fail");
+
+        assert_eq!(kept(HeaderDupPolicy::KeepAll), (vec!["Title=A".to_string(), "Expires=1 day".to_string(), "Title=B".to_string()], 0));
+        assert_eq!(kept(HeaderDupPolicy::Warn), (vec!["Title=A".to_string(), "Expires=1 day".to_string()], 1));
+        assert_eq!(kept(HeaderDupPolicy::LastWins), (vec!["Expires=1 day".to_string(), "Title=B".to_string()], 0));
+        assert!(matches!(headers(HeaderDupPolicy::Error), Err(CompileError::DuplicateHeader { key }) if key == "Title"));
+    }
+
+    #[test]
+    fn duplicate_headers_are_kept_by_default() {
+        let options = CompileOptions {
+            header_attributes: vec!["Title=A".parse().expect("header must be valid"), "Title=B".parse().expect("header must be valid")],
+            ..CompileOptions::new(CompileTarget::UBlockOrigin, vec![GenerateTargetPlatform::Base])
+        };
+
+        assert_eq!(compile_with(&options, "[]").expect("compile must succeed"), "! Title: A\n! Title: B\n");
+    }
+
+    #[test]
+    fn substring_is_unanchored() {
+        let json = r#"[{"type": "domain", "match": "substring", "domain": "tracker"}]"#;
+
+        assert_eq!(compile(CompileTarget::UBlockOrigin, json), "*tracker*\n");
+        assert_eq!(compile(CompileTarget::UBlackList, json), "*://*tracker*\n");
+    }
+
+    #[test]
+    fn substring_must_be_plain_and_long_enough() {
+        let entry = |value: &str| list(&format!(r#"[{{"type": "domain", "match": "substring", "domain": "{value}"}}]"#)).0.remove(0);
+
+        assert!(matches!(check_entry(&entry("track*er")), Err(SyntaxCheckError::InvalidSubstring { .. })));
+        assert!(matches!(check_entry(&entry("")), Err(SyntaxCheckError::InvalidSubstring { .. })));
+        assert!(entry_warning(&entry("ad")).is_some());
+        assert!(entry_warning(&entry("tracker")).is_none());
+    }
+
+    #[test]
+    fn lines_are_literal_domains() {
+        let list = parse_text("# comment\n\n  a.com  \n! comment\nb.com\n", InputFormat::Lines).expect("lines must be parsed");
+
+        assert_eq!(list.0, self::list(r#"[
+            {"type": "domain", "match": "literal", "domain": "a.com"},
+            {"type": "domain", "match": "literal", "domain": "b.com"}
+        ]"#).0);
+        assert!(matches!(list.0[1].provenance().and_then(|x| x.position), Some(Position::Line(5))));
+        assert_eq!(InputFormat::detect(Path::new("legacy.txt")), InputFormat::Lines);
+    }
+
+    #[test]
+    fn suffix_matches_the_end_of_the_host() {
+        let rule = host_suffix_regex_rule("example.org");
+
+        // no label boundary is required
+        assert!(regex_rule_matches(&rule, "https://adexample.org/"));
+        assert!(regex_rule_matches(&rule, "https://a.example.org:443/path"));
+        assert!(!regex_rule_matches(&rule, "https://example.org.evil.com/"));
+        assert!(!regex_rule_matches(&rule, "https://a.com/example.org"));
+        assert!(!regex_rule_matches(&rule, "https://exampleXorg/"));
+
+        let json = r#"[{"type": "domain", "match": "suffix", "domain": "example.org"}]"#;
+        assert_eq!(compile(CompileTarget::UBlockOrigin, json), format!("{rule}\n"));
+        assert_eq!(compile(CompileTarget::UBlackList, json), format!("{rule}\n"));
+    }
+
+    #[test]
+    fn prefix_matches_the_start_of_the_path() {
+        let rule = path_prefix_regex_rule("/ads/");
+
+        assert!(regex_rule_matches(&rule, "https://a.com/ads/banner.png"));
+        assert!(regex_rule_matches(&rule, "http://b.com:8080/ads/"));
+        assert!(!regex_rule_matches(&rule, "https://a.com/static/ads/"));
+
+        let json = r#"[
+            {"type": "path", "match": "prefix", "path": "/ads/"},
+            {"type": "path", "match": "prefix", "path": "b.com/ads"}
+        ]"#;
+        assert_eq!(compile(CompileTarget::UBlockOrigin, json), format!("{rule}\n||b.com/ads\n"));
+        assert_eq!(compile(CompileTarget::UBlackList, json), "*://*/ads/*\n*://b.com/ads*\n");
+    }
+
+    #[test]
+    fn prefix_is_only_for_paths() {
+        let entry = list(r#"[{"type": "domain", "match": "prefix", "domain": "a.com"}]"#).0.remove(0);
+
+        assert!(matches!(check_entry(&entry), Err(SyntaxCheckError::InvalidPrefix { .. })));
+    }
+
+    #[test]
+    fn jsonc_comments_are_stripped_outside_of_strings() {
+        let jsonc = r#"[
+            // line comment
+            {"type": "path", "match": "literal", "path": "a.com//b/*c*/"}, /* block
+            comment */
+            {"type": "domain", "match": "literal", "domain": "b.com"}
+        ]"#;
+
+        assert_eq!(parse_text(jsonc, InputFormat::Jsonc).expect("JSONC must be parsed").0, list(r#"[
+            {"type": "path", "match": "literal", "path": "a.com//b/*c*/"},
+            {"type": "domain", "match": "literal", "domain": "b.com"}
+        ]"#).0);
+    }
+
+    #[test]
+    fn jsonc_error_points_into_the_original_text() {
+        let jsonc = "[\n  /* a\n  comment */ {\"type\": \"domain\" \"match\": \"literal\"}\n]";
+
+        let Err(SyntaxCheckError::Deserialize { source, snippet }) = parse_text(jsonc, InputFormat::Jsonc) else {
+            panic!("JSONC must be invalid")
+        };
+        // at `"match"`, which has no comma before it
+        assert_eq!((source.line(), source.column()), (3, 32));
+        assert!(snippet.contains("\"match\""), "{snippet}");
+    }
+
+    #[test]
+    fn numbered_path_inserts_the_number_before_every_extension() {
+        assert_eq!(numbered_path(Path::new("out/list.txt.gz"), 2), Path::new("out/list.2.txt.gz"));
+        assert_eq!(numbered_path(Path::new("list"), 1), Path::new("list.1"));
+    }
+
+    #[test]
+    fn split_output_repeats_the_header_in_every_file() {
+        let dir = std::env::temp_dir().join(format!("exclude_entry_compiler-split-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("temporary directory must be writable");
+        let input = dir.join("list.json");
+        let entries = (1..=5).map(|i| format!(r#"{{"type": "domain", "match": "literal", "domain": "a{i}.com"}}"#)).collect::<Vec<_>>().join(",");
+        std::fs::write(&input, format!("[{entries}]")).expect("input must be writable");
+        let options = CompileOptions {
+            header_attributes: vec!["Title=T".parse().expect("header must be valid")],
+            ..CompileOptions::new(CompileTarget::UBlockOrigin, vec![GenerateTargetPlatform::Base])
+        };
+
+        let (_, files) = compile_split(&[input], &dir.join("out.txt"), &options, NonZeroUsize::new(2).expect("non-zero")).expect("compile must succeed");
+        let read = |n| std::fs::read_to_string(dir.join(format!("out.{n}.txt"))).expect("output must be written");
+
+        assert_eq!(files, 3);
+        assert_eq!(read(1), "! Title: T\n||a1.com^\n||a2.com^\n");
+        assert_eq!(read(2), "! Title: T\n||a3.com^\n||a4.com^\n");
+        assert_eq!(read(3), "! Title: T\n||a5.com^\n");
+        std::fs::remove_dir_all(&dir).expect("temporary directory must be removable");
+    }
+
+    #[test]
+    fn ndjson_has_one_entry_per_line() {
+        let ndjson = "{\"type\": \"domain\", \"match\": [\"literal\", \"wildcard\"], \"domain\": \"a.com\"}\n\n{\"type\": \"comment\", \"text\": \"ads\"}\n";
+        let list = parse_text(ndjson, InputFormat::Ndjson).expect("NDJSON must be parsed");
+
+        assert_eq!(list.0.len(), 3);
+        assert!(list.0[..2].iter().all(|x| matches!(x.provenance().and_then(|x| x.position), Some(Position::Line(1)))));
+        assert_eq!(list.0[2], Entry::Comment { text: "ads".to_string() });
+    }
+
+    #[test]
+    fn ndjson_error_names_the_line() {
+        let mut entries = read_ndjson_entries("{\"type\": \"comment\", \"text\": \"ads\"}\n[]\n".as_bytes());
+
+        assert!(entries.next().is_some_and(|x| x.is_ok()));
+        assert!(matches!(entries.next(), Some(Err(SyntaxCheckError::DeserializeLine { line_number: 2, .. }))));
+    }
+
+    #[test]
+    fn expand_env_replaces_defined_variables_only() {
+        // unique to this test, since tests share the environment
+        std::env::set_var("EXCLUDE_ENTRY_COMPILER_TEST_VERSION", "1.2.3");
+        let expand = |value: &str| expand_env_vars(format!("Version={value}").parse().expect("header must be valid")).map(|x| x.value);
+
+        assert_eq!(expand("v${EXCLUDE_ENTRY_COMPILER_TEST_VERSION} ($ and {} kept)").ok().as_deref(), Some("v1.2.3 ($ and {} kept)"));
+        assert!(matches!(expand("${EXCLUDE_ENTRY_COMPILER_TEST_UNDEFINED}"), Err(CompileError::HeaderExpansion { .. })));
+        assert!(matches!(expand("${EXCLUDE_ENTRY_COMPILER_TEST_VERSION"), Err(CompileError::HeaderExpansion { .. })));
+
+        // not expanded unless asked
+        let options = |expand_env| CompileOptions {
+            header_attributes: vec!["Version=${EXCLUDE_ENTRY_COMPILER_TEST_VERSION}".parse().expect("header must be valid")],
+            expand_env,
+            ..CompileOptions::new(CompileTarget::UBlockOrigin, vec![GenerateTargetPlatform::Base])
+        };
+        assert_eq!(compile_with(&options(false), "[]").expect("compile must succeed"), "! Version: ${EXCLUDE_ENTRY_COMPILER_TEST_VERSION}\n");
+        assert_eq!(compile_with(&options(true), "[]").expect("compile must succeed"), "! Version: 1.2.3\n");
+    }
+
+    #[test]
+    fn rule_limit_per_line_groups_values_of_single_value_rules() {
+        let json = r#"[
+            {"type": "domain", "match": "literal", "domain": "a.com"},
+            {"type": "domain", "match": "literal", "domain": "b.com"},
+            {"type": "domain", "match": "literal", "domain": "c.com", "note": "x"},
+            {"type": "domain", "match": "literal", "domain": "d.com"},
+            {"type": "domain", "match": "literal", "domain": "e.com"}
+        ]"#;
+        let options = |target| CompileOptions {
+            rule_limit_per_line: NonZeroUsize::new(2),
+            ..CompileOptions::new(target, vec![GenerateTargetPlatform::Base])
+        };
+        let compile = |target| compile_with(&options(target), json).expect("fixture must compile");
+
+        // hosts writes a note after its rule, which is then emitted alone
+        assert_eq!(compile(CompileTarget::Hosts), "0.0.0.0 a.com b.com\n0.0.0.0 c.com # x\n0.0.0.0 d.com e.com\n");
+        assert_eq!(compile(CompileTarget::Dnsmasq), "address=/a.com/b.com/0.0.0.0\n# x\naddress=/c.com/d.com/0.0.0.0\naddress=/e.com/0.0.0.0\n");
+        // ignored with a warning
+        assert_eq!(compile(CompileTarget::UBlockOrigin), "||a.com^\n||b.com^\n||c.com^ # x\n||d.com^\n||e.com^\n");
+    }
+}
@@ -1,124 +1,295 @@
 #![deny(clippy::all)]
 #![warn(clippy::pedantic, clippy::nursery)]
 
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::fmt::Write;
+use std::io::IsTerminal;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
-use std::process::{exit, ExitCode};
+use std::process::ExitCode;
 use std::str::FromStr;
-use clap::Parser;
+use clap::{ColorChoice, Parser, Subcommand};
 use serde::Deserialize;
-use serde_with::DeserializeFromStr;
-use strum::EnumString;
+use strum::VariantNames;
 use thiserror::Error;
+use exclude_entry_compiler::{
+    AdGuardModifier, AutoHeaderField, CompileError, CompileTarget, DecompileError, DedupMode, EntryType, GenerateTargetPlatform, HeaderAttribute, HeaderDupPolicy, InputFormat, IsoDate, LineEnding, OutputFormat,
+    OutputCompression, Position, Scheme, SyntaxCheckError,
+};
 
-#[derive(Deserialize)]
-struct EntryList(Vec<Entry>);
-
-#[derive(Deserialize)]
-#[serde(tag = "type")]
-enum Entry {
-    #[serde(rename = "domain")]
-    Domain {
-        #[serde(rename = "match")]
-        match_method: MatchMethod,
-        domain: String,
-    },
-    #[serde(rename = "path")]
-    Path {
-        #[serde(rename = "match")]
-        match_method: MatchMethod,
-        path: String,
-    }
+#[derive(Parser)]
+struct Cli {
+    #[clap(long, global = true, default_value = "auto")]
+    /// Colors error messages: 'auto', 'always', or 'never'. 'auto' colors only if stderr is a terminal and `NO_COLOR` is unset.
+    color: ColorChoice,
+    #[clap(subcommand)]
+    command: Args,
 }
 
-#[derive(Parser)]
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+// parsed only once
 enum Args {
+    // `-h` is taken by --header-attributes
+    #[clap(disable_help_flag = true)]
     Compile {
-        #[clap(short = 't', long)]
-        target: CompileTarget,
-        #[clap(short = 'f', long = "feature", long)]
+        #[clap(short = 't', long, required = true, value_parser = parse_listed::<CompileTarget>)]
+        /// Target. May specify more than once with --output-dir.
+        target: Vec<CompileTarget>,
+        #[clap(short = 'f', long = "feature", long, value_parser = parse_listed::<GenerateTargetPlatform>)]
         feature_flag: Vec<GenerateTargetPlatform>,
-        #[clap(short = 'i', long = "in", long = "input", long)]
-        input_file: PathBuf,
-        #[clap(short = 'o', long = "out", long = "output", long)]
-        output_file: PathBuf,
+        #[clap(short = 'i', long = "in", long = "input", long, default_value = "-")]
+        /// Input file. '-' or omitting this reads from stdin. May specify more than once; entries are concatenated in order.
+        input_file: Vec<PathBuf>,
+        #[clap(long, conflicts_with = "input_file")]
+        /// Glob pattern of input files, such as 'entries/*.json'. Matched files are loaded in sorted order.
+        input_glob: Option<String>,
+        #[clap(long)]
+        /// Input format: 'json', 'jsonc', 'yaml', 'toml', 'lines', or 'ndjson'. Detected from the file extension if omitted.
+        format: Option<InputFormat>,
+        #[clap(long)]
+        /// Trims whitespace around domain, path, IP, and CIDR values before validation. Empty values are rejected
+        trim_whitespace: bool,
+        #[clap(short = 'o', long = "out", long = "output", long, required_unless_present_any = ["count_only", "check", "output_dir"])]
+        /// Output file. '-' writes to stdout.
+        output_file: Option<PathBuf>,
+        #[clap(long, conflicts_with = "output_file")]
+        /// Output directory. Each target is written to its own file, such as 'ublockorigin.txt'.
+        output_dir: Option<PathBuf>,
         #[clap(short = 'h', long = "header", long)]
         /// Header attributes. Format: 'K=V'
         header_attributes: Vec<HeaderAttribute>,
+        #[clap(long)]
+        /// File of header attributes, one 'K=V' per line. They are emitted before '-h'.
+        header_file: Option<PathBuf>,
+        #[clap(long)]
+        /// Adds 'Last modified' (UTC timestamp) and 'Entries' (entry count) headers.
+        auto_header: bool,
+        #[clap(long, requires = "auto_header")]
+        /// Omits an automatic header. Supported: 'last-modified', 'entries'
+        auto_header_exclude: Vec<AutoHeaderField>,
+        #[clap(long)]
+        /// File written verbatim before the header attributes, such as license text.
+        prepend_file: Option<PathBuf>,
+        #[clap(long)]
+        /// File written verbatim after the generated rules.
+        append_file: Option<PathBuf>,
+        #[clap(long, default_value = "keep-all")]
+        /// How to handle header attributes sharing the same key: 'error', 'warn' (keeps the first), 'last-wins', or 'keep-all'
+        header_dup_policy: HeaderDupPolicy,
+        #[clap(long)]
+        /// Replaces '${VAR}' in header values with the environment variable VAR. Fails if it is not defined.
+        expand_env: bool,
+        #[clap(long = "adguard-modifier")]
+        /// Modifiers appended to each rule of `AdGuard` target. Format: 'important' or 'dnsrewrite=V'
+        adguard_modifiers: Vec<AdGuardModifier>,
+        #[clap(long)]
+        /// Fails if more entries than this are loaded. Unlimited if omitted.
+        max_entries: Option<usize>,
+        #[clap(long)]
+        /// Includes entries whose 'enabled' is false. They are skipped by default.
+        include_disabled: bool,
+        #[clap(long, alias = "since")]
+        /// Keeps only entries whose 'added' is on or after this date (YYYY-MM-DD). Entries without a date are skipped.
+        modified_after: Option<IsoDate>,
+        #[clap(long, value_parser = parse_listed::<EntryType>)]
+        /// Keeps only entries of this type. May specify more than once
+        only_type: Vec<EntryType>,
+        #[clap(long)]
+        /// Expands CIDR entries into IP entries. Fails for a range larger than 65536 addresses.
+        expand_cidr: bool,
+        #[clap(long)]
+        /// Lowercases domains and the host of paths. Path components after the host and regex entries are kept as is.
+        lowercase: bool,
+        #[clap(long)]
+        /// Strips a single trailing dot from domains ('example.com.' to 'example.com'). It is kept as is otherwise.
+        normalize_trailing_dot: bool,
+        #[clap(long)]
+        /// Converts internationalized domains and the host of paths into punycode ('xn--').
+        punycode: bool,
+        #[clap(long)]
+        /// Removes a leading 'www.' from literal and wildcard domain entries. Note that for `uBlockOrigin` and `AdGuard`,
+        /// '||example.com^' also matches every subdomain, so this widens the match.
+        strip_www: bool,
+        #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "exact")]
+        /// Removes duplicated entries. The first occurrence is kept. '--dedup=semantic' also removes entries covered by
+        /// a wildcard domain, such as 'sub.example.com' by '*.example.com'
+        dedup: Option<DedupMode>,
+        #[clap(long)]
+        /// Sorts entries case-insensitively. Sort order is by the matched value (domain or path), not by entry type.
+        sort: bool,
+        #[clap(long)]
+        /// Groups rules of the Base section by entry type (domains, paths, IPs, then CIDRs), each under a comment banner.
+        /// With '--sort', rules are sorted within each group. Comment entries are skipped.
+        group_by_type: bool,
+        #[clap(long)]
+        /// Joins up to N rules into one line for targets accepting several values per line (hosts and dnsmasq).
+        /// Ignored with a warning for the other targets.
+        rule_limit_per_line: Option<NonZeroUsize>,
+        #[clap(long)]
+        /// Emits a comment naming the input file and the position of the entry before each rule, such as '! source: list.json entry 3'.
+        emit_metadata_comment: bool,
+        #[clap(long)]
+        /// Fails if no rule would be generated, instead of succeeding with an empty output.
+        fail_on_empty: bool,
+        #[clap(long)]
+        /// Fails if any warning is emitted, including ones printed only with '--verbose'. The output is still written
+        fail_on_warning: bool,
+        #[clap(long)]
+        /// Compresses the output. Supported: 'gzip'
+        compress: Option<OutputCompression>,
+        #[clap(long)]
+        /// Overrides the comment prefix of the target, used for headers, comment entries, and notes
+        comment_prefix: Option<String>,
+        #[clap(long)]
+        /// Replaces the rule of every entry in the Base section, such as '||{value}^'. '{value}' is substituted with
+        /// the domain, path, IP address, or CIDR. Exception entries are skipped
+        template: Option<String>,
+        #[clap(long, default_value = "lf")]
+        /// Line break of the output: 'lf' or 'crlf'
+        line_ending: LineEnding,
+        #[clap(long, default_value = "text")]
+        /// Output format: 'text' (the filter list) or 'json-lines' (one JSON object per rule, without headers and comments)
+        output_format: OutputFormat,
+        #[clap(long, default_value = "any")]
+        /// Scheme matched by uBlackList rules: 'any', 'https', or 'http'. Other targets always match any scheme
+        scheme: Scheme,
+        #[clap(long)]
+        /// Also emits '*://*.example.com/*' for literal domains of uBlackList, so that subdomains are blocked as in uBlockOrigin
+        include_subdomains: bool,
+        #[clap(long, default_value_t = 1)]
+        /// Number of threads used to serialize rules. '0' uses every CPU. The output does not depend on this.
+        jobs: usize,
+        #[clap(long)]
+        /// Shows a progress bar of serialized entries on stderr. Hidden if stderr is not a terminal
+        progress: bool,
+        #[clap(long)]
+        /// Splits the output into numbered files ('out.1.txt', 'out.2.txt', ...) of at most N generated lines each.
+        /// Header lines are repeated in every file
+        split_output: Option<NonZeroUsize>,
+        #[clap(long)]
+        /// Prints the number of lines per section instead of writing the output.
+        count_only: bool,
+        #[clap(long, conflicts_with = "count_only")]
+        /// Runs every validation and transformation of compile without writing the output.
+        check: bool,
+        #[clap(long, conflicts_with_all = ["count_only", "check", "watch", "compress"])]
+        /// Prints the difference between the output and the existing output file to stderr, without writing it.
+        /// Fails if they differ
+        dry_run: bool,
+        #[clap(long)]
+        /// Recompiles whenever an input file is modified, until interrupted. Errors are printed without exiting.
+        watch: bool,
+        #[clap(long)]
+        /// Writes a JSON report of the run, such as line counts per section and warnings.
+        report_json: Option<PathBuf>,
         #[clap(short = 'v', long)]
         verbose: bool,
+        #[clap(short = 'q', long, conflicts_with = "verbose")]
+        /// Prints nothing but fatal errors.
+        quiet: bool,
+        #[clap(long, action = clap::ArgAction::Help)]
+        /// Print help.
+        help: Option<bool>,
     },
     Check {
+        #[clap(default_value = "-")]
+        /// Input file. '-' or omitting this reads from stdin.
         input_file: PathBuf,
+        #[clap(long)]
+        /// Input format: 'json', 'jsonc', 'yaml', 'toml', 'lines', or 'ndjson'. Detected from the file extension if omitted.
+        format: Option<InputFormat>,
+        #[clap(short = 'q', long)]
+        /// Prints nothing but fatal errors.
+        quiet: bool,
+        #[clap(long)]
+        /// Prints every problem as a JSON array of diagnostics to stdout, instead of failing on the first one.
+        json: bool,
     },
+    /// Converts a compiled list back into a JSON entry list. Only simple block rules are recognized.
+    Decompile {
+        #[clap(short = 't', long, value_parser = parse_listed::<CompileTarget>)]
+        target: CompileTarget,
+        #[clap(short = 'i', long = "input", default_value = "-")]
+        /// Input file. '-' or omitting this reads from stdin.
+        input_file: PathBuf,
+        #[clap(short = 'o', long = "output", default_value = "-")]
+        /// Output file. '-' or omitting this writes to stdout.
+        output_file: PathBuf,
+        #[clap(short = 'q', long)]
+        /// Does not report unrecognized lines.
+        quiet: bool,
+    },
+    /// Compiles two entry lists and prints the difference of their outputs in unified diff format.
+    Diff {
+        /// Entry list before the change.
+        old_input: PathBuf,
+        /// Entry list after the change.
+        new_input: PathBuf,
+        #[clap(short = 't', long, value_parser = parse_listed::<CompileTarget>)]
+        target: CompileTarget,
+        #[clap(short = 'f', long = "feature", long, value_parser = parse_listed::<GenerateTargetPlatform>)]
+        feature_flag: Vec<GenerateTargetPlatform>,
+        #[clap(long)]
+        /// Input format: 'json', 'jsonc', 'yaml', 'toml', 'lines', or 'ndjson'. Detected from the file extension if omitted.
+        format: Option<InputFormat>,
+    },
+    /// Compiles every job of a manifest. Failed jobs do not stop the others.
+    Batch {
+        /// JSON or TOML (by the '.toml' extension) manifest. See README for the format.
+        manifest: PathBuf,
+        #[clap(short = 'v', long)]
+        verbose: bool,
+        #[clap(short = 'q', long, conflicts_with = "verbose")]
+        /// Prints nothing but errors.
+        quiet: bool,
+    },
+    /// Prints JSON Schema of the entry list.
+    Schema,
 }
 
-#[derive(Clone, Eq, PartialEq)]
-struct HeaderAttribute {
-    key: String,
-    value: String,
-}
-
-impl FromStr for HeaderAttribute {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (key, value) = s.split_once('=').ok_or(())?;
-        Ok(Self {
-            key: key.to_string(),
-            value: value.to_string(),
-        })
-    }
-}
-
-impl From<&str> for HeaderAttribute {
-    fn from(value: &str) -> Self {
-        <Self as FromStr>::from_str(value).expect("!!")
-    }
-}
-
-#[derive(EnumString, Copy, Clone, Eq, PartialEq)]
-enum CompileTarget {
-    #[strum(serialize = "uBlackList")]
-    UBlackList,
-    #[strum(serialize = "uBlockOrigin")]
-    UBlockOrigin,
-}
-
-#[derive(EnumString, Copy, Clone, Eq, PartialEq)]
-enum GenerateTargetPlatform {
-    Base,
-    /// Generates Google search block rule. Match if and only if the URL prefix matches in deny list entry.
-    GoogleSearchPrefix,
-    /// Also generates Google search block rule. Match if and only if the URL contains deny list entry.
-    GoogleSearchFuzzy,
+/// Manifest of the `batch` subcommand. Relative paths are resolved from the directory of the manifest.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    job: Vec<Job>,
 }
 
-#[derive(EnumString, Copy, Clone, Eq, PartialEq, DeserializeFromStr)]
-enum MatchMethod {
-    #[strum(serialize = "literal")]
-    Literal
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Job {
+    input: Vec<PathBuf>,
+    target: CompileTarget,
+    #[serde(default)]
+    feature_flags: Vec<GenerateTargetPlatform>,
+    output: PathBuf,
+    /// `K=V`
+    #[serde(default)]
+    header: Vec<HeaderAttribute>,
 }
 
 #[derive(Error, Debug)]
-enum CompileError {
-    #[error("JSON Deserialize error: {0}")]
-    Deserialize(#[from] serde_json::Error),
-    #[error("I/O error: {0}")]
+enum ManifestError {
+    #[error("{0}")]
     Io(#[from] std::io::Error),
-    #[error("Unsupported feature combination")]
-    UnsupportedFeatureSet,
-    #[error("Syntax error: {0}")]
-    Syntax(#[from] SyntaxCheckError),
+    #[error("JSON Deserialize error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("TOML Deserialize error: {0}")]
+    Toml(#[from] toml::de::Error),
 }
 
 #[derive(Error, Debug)]
-enum SyntaxCheckError {
-    #[error("JSON Deserialize error: {0}")]
-    Deserialize(#[from] serde_json::Error),
-    #[error("I/O error: {0}")]
-    Io(#[from] std::io::Error)
+enum InputGlobError {
+    #[error("invalid pattern: {0}")]
+    Pattern(#[from] glob::PatternError),
+    #[error("{0}")]
+    Io(#[from] glob::GlobError),
+    #[error("no file matches")]
+    NoMatch,
+}
+
+/// Parses `T` case-insensitively, listing every accepted value on failure.
+fn parse_listed<T: FromStr + VariantNames>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("expected one of {}", T::VARIANTS.join(", ")))
 }
 
 #[derive(Error, Debug)]
@@ -127,185 +298,641 @@ enum ExecutionError {
     Compile(#[from] CompileError),
     #[error("Failed to syntax check: {0}")]
     Check(#[from] SyntaxCheckError),
+    #[error("Failed to decompile: {0}")]
+    Decompile(#[from] DecompileError),
+    #[error("Failed to watch inputs: {0}")]
+    Watch(#[from] notify::Error),
+    #[error("Output is out of date: {}", .outputs.iter().map(|x| x.display().to_string()).collect::<Vec<_>>().join(", "))]
+    OutOfDate {
+        outputs: Vec<PathBuf>,
+    },
+    #[error("Failed to compile {} of {total} targets:{}", .failures.len(), format_target_failures(.failures))]
+    Targets {
+        failures: Vec<(CompileTarget, CompileError)>,
+        total: usize,
+    },
+    #[error("Failed to read manifest: {0}")]
+    Manifest(#[from] ManifestError),
+    /// Each error has been printed as a diagnostic.
+    #[error("{errors} errors were found")]
+    InvalidEntries {
+        errors: usize,
+    },
+    #[error("Failed to expand --input-glob '{pattern}': {source}")]
+    InputGlob {
+        pattern: String,
+        #[source]
+        source: InputGlobError,
+    },
+    #[error("Failed to write output: {0}")]
+    Output(#[from] std::io::Error),
+    /// Each failure has been reported when the job finished.
+    #[error("Failed {} of {total} jobs", .failures.len())]
+    Batch {
+        failures: Vec<CompileError>,
+        total: usize,
+    },
+}
+
+fn format_target_failures(failures: &[(CompileTarget, CompileError)]) -> String {
+    failures.iter().fold(String::new(), |mut buf, (target, e)| {
+        // writing into a String never fails
+        let _ = write!(buf, "\n  {target}: {e}");
+        buf
+    })
+}
+
+impl ExecutionError {
+    /// Stable exit code for scripts. See README for the table.
+    fn exit_code(&self) -> u8 {
+        match self {
+            Self::Compile(e) => compile_error_exit_code(e),
+            Self::Check(e) => syntax_check_error_exit_code(e),
+            Self::Decompile(DecompileError::Serialize(_)) => 1,
+            Self::Decompile(DecompileError::Io(_)) | Self::Watch(_) | Self::Output(_) | Self::Manifest(ManifestError::Io(_)) | Self::InputGlob { source: InputGlobError::Io(_), .. } => 4,
+            Self::Manifest(ManifestError::Json(_) | ManifestError::Toml(_)) | Self::InputGlob { source: InputGlobError::NoMatch, .. } | Self::InvalidEntries { .. } => 3,
+            Self::Targets { failures, .. } => common_exit_code(failures.iter().map(|(_, e)| e)),
+            Self::Batch { failures, .. } => common_exit_code(failures.iter()),
+            Self::OutOfDate { .. } => 8,
+            Self::InputGlob { source: InputGlobError::Pattern(_), .. } => 2,
+        }
+    }
+
+    /// Error category shown before the message. Derived from the exit code so that both agree.
+    fn category(&self) -> &'static str {
+        match self.exit_code() {
+            2 => "usage",
+            3 => "invalid input",
+            4 => "I/O",
+            5 => "unsupported",
+            6 => "nothing to generate",
+            7 => "limit",
+            8 => "out of date",
+            9 => "warning",
+            _ => "error",
+        }
+    }
+
+    /// Input file and position of a syntax error, such as `list.json:3:14` or `list.json, entry 3`. Either part may be missing.
+    fn location(&self) -> Option<String> {
+        let (path, e) = self.syntax_error()?;
+        if let SyntaxCheckError::Positioned { position: Position::Index(index), .. } = e {
+            return Some(path.map_or_else(|| format!("entry {index}"), |path| format!("{}, entry {index}", path.display())))
+        }
+
+        let position = match e {
+            SyntaxCheckError::DeserializeLine { line_number, .. } | SyntaxCheckError::Positioned { position: Position::Line(line_number), .. } => Some((*line_number, None)),
+            _ => self.parser_position().map(|(line, column)| (line, Some(column))),
+        };
+
+        match (path, position) {
+            (Some(path), Some((line, Some(column)))) => Some(format!("{}:{line}:{column}", path.display())),
+            (Some(path), Some((line, None))) => Some(format!("{}:{line}", path.display())),
+            (Some(path), None) => Some(path.display().to_string()),
+            (None, Some((line, Some(column)))) => Some(format!("line {line}, column {column}")),
+            (None, Some((line, None))) => Some(format!("line {line}")),
+            (None, None) => None,
+        }
+    }
+
+    /// Syntax error and the input file which has it, if this is caused by one.
+    const fn syntax_error(&self) -> Option<(Option<&PathBuf>, &SyntaxCheckError)> {
+        match self {
+            Self::Compile(CompileError::Input { path, source }) => Some((Some(path), source)),
+            Self::Compile(CompileError::Syntax(e)) | Self::Check(e) => Some((None, e)),
+            _ => None,
+        }
+    }
+
+    /// Line and column which the JSON or YAML parser reports, and also appends to its message as `at line 3 column 14`.
+    fn parser_position(&self) -> Option<(usize, usize)> {
+        match self.syntax_error()?.1 {
+            SyntaxCheckError::Deserialize { source, .. } => Some((source.line(), source.column())),
+            SyntaxCheckError::Yaml(e) => e.location().map(|x| (x.line(), x.column())),
+            _ => None,
+        }
+    }
+
+    /// Renders the category, the message, the location, and the innermost cause, one per line.
+    /// The cause is omitted if the message already contains it, and the position of the parser is only shown as the location.
+    fn report(&self, color: bool) -> String {
+        let paint = |code: &str, text: &str| if color { format!("\x1b[{code}m{text}\x1b[0m") } else { text.to_string() };
+        let location = self.location();
+        let strip_position = |text: String| match self.parser_position() {
+            Some((line, column)) if location.is_some() => text.replacen(&format!(" at line {line} column {column}"), "", 1),
+            _ => text,
+        };
+
+        let message = strip_position(self.to_string());
+        let mut buf = format!("{} {message}\n", paint("1;31", &format!("error[{}]:", self.category())));
+        if let Some(location) = &location {
+            let _ = writeln!(buf, "  {} {location}", paint("1;34", "-->"));
+        }
+
+        if let Some(mut cause) = std::error::Error::source(self) {
+            while let Some(x) = cause.source() {
+                cause = x;
+            }
+
+            let cause = strip_position(cause.to_string());
+            if !message.contains(&cause) {
+                let _ = writeln!(buf, "  {} {cause}", paint("1;33", "cause:"));
+            }
+        }
+
+        buf
+    }
+}
+
+/// Resolves `--color auto` by whether stderr is a terminal and by `NO_COLOR`.
+fn use_color(choice: ColorChoice) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|x| x.is_empty()),
+    }
+}
+
+/// Exit code shared by every error, or 1 if they differ.
+fn common_exit_code<'a>(errors: impl Iterator<Item = &'a CompileError>) -> u8 {
+    let mut codes = errors.map(compile_error_exit_code);
+    let first = codes.next().unwrap_or(1);
+    if codes.all(|x| x == first) { first } else { 1 }
+}
+
+const fn compile_error_exit_code(e: &CompileError) -> u8 {
+    match e {
+        CompileError::ThreadPool(_) => 1,
+        CompileError::DuplicateHeader { .. } | CompileError::InvalidTemplate { .. } | CompileError::HeaderExpansion { .. } => 2,
+        CompileError::Deserialize(_) | CompileError::InvalidIdn { .. } | CompileError::InvalidHeaderFile { .. } => 3,
+        CompileError::Io(_) | CompileError::AuxiliaryFile { .. } => 4,
+        CompileError::UnsupportedFeatureSet | CompileError::ConflictingFeatures { .. } => 5,
+        CompileError::NothingToGenerate => 6,
+        CompileError::WarningsAsErrors { .. } => 9,
+        CompileError::TooManyEntries { .. } | CompileError::CidrTooLarge { .. } => 7,
+        CompileError::Syntax(e) | CompileError::Input { source: e, .. } => syntax_check_error_exit_code(e),
+    }
+}
+
+const fn syntax_check_error_exit_code(e: &SyntaxCheckError) -> u8 {
+    match e {
+        SyntaxCheckError::Io(_) => 4,
+        SyntaxCheckError::Deserialize { .. }
+        | SyntaxCheckError::DeserializeLine { .. }
+        | SyntaxCheckError::Yaml(_)
+        | SyntaxCheckError::Toml(_)
+        | SyntaxCheckError::InvalidRegex { .. }
+        | SyntaxCheckError::InvalidWildcard { .. }
+        | SyntaxCheckError::InvalidDomain { .. }
+        | SyntaxCheckError::InvalidPath { .. }
+        | SyntaxCheckError::InvalidNote { .. }
+        | SyntaxCheckError::InvalidComment { .. }
+        | SyntaxCheckError::InvalidDate { .. }
+        | SyntaxCheckError::InvalidFeatureFlag { .. }
+        | SyntaxCheckError::InvalidModifier { .. }
+        | SyntaxCheckError::EmptyValue { .. }
+        | SyntaxCheckError::EmptyPattern
+        | SyntaxCheckError::PathWithScheme { .. }
+        | SyntaxCheckError::AnchorCharacter { .. }
+        | SyntaxCheckError::InvalidIp { .. }
+        | SyntaxCheckError::InvalidCidr { .. }
+        | SyntaxCheckError::InvalidSubstring { .. }
+        | SyntaxCheckError::InvalidSuffix { .. }
+        | SyntaxCheckError::InvalidPrefix { .. }
+        | SyntaxCheckError::ConflictingEntries { .. }
+        | SyntaxCheckError::Positioned { .. } => 3,
+    }
 }
 
 fn main() -> ExitCode {
-    let x = imp::main();
+    let Cli { color, command } = Cli::parse();
+    let x = imp::main(command);
 
     if let Err(e) = x {
-        eprintln!("{e}");
-        ExitCode::from(1)
+        eprint!("{}", e.report(use_color(color)));
+        ExitCode::from(e.exit_code())
     } else {
         ExitCode::from(0)
     }
 }
 
 mod imp {
-    use clap::Parser;
-    use crate::{Args, compile, ExecutionError, syntax_check};
+    use clap::error::ErrorKind;
+    use clap::CommandFactory;
+    use std::io::Write;
+    use std::path::Path;
+    use exclude_entry_compiler::{
+        compile, compile_split, compile_stats, conflicting_feature_flags, compile_to_bytes, compile_to_writer, decompile_file, entry_list_schema, AutoHeaderField, CompileError, CompileOptions, CompileReport, CompileStats, CompileTarget,
+        GenerateTargetPlatform, InputFormat, LogLevel, Severity, syntax_check, syntax_check_report,
+    };
+    use std::num::NonZeroUsize;
+    use std::path::PathBuf;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use notify::{Event, RecursiveMode, Watcher};
+    use similar::TextDiff;
+    use crate::{Args, Cli, ExecutionError, InputGlobError, Manifest, ManifestError};
 
     #[allow(clippy::redundant_pub_crate)]
     // ExecutionError must be pub if this vis is also pub
-    pub(crate) fn main() -> Result<(), ExecutionError> {
-        let args = Args::parse();
+    pub(crate) fn main(args: Args) -> Result<(), ExecutionError> {
         match args {
-            Args::Compile { target, feature_flag, input_file, output_file, header_attributes, verbose } => {
-                compile(input_file, target, &feature_flag, output_file, &header_attributes, verbose)?;
+            Args::Compile { target: targets, feature_flag, input_file, input_glob, format, trim_whitespace, output_file, output_dir, header_attributes, header_file, auto_header, auto_header_exclude, header_dup_policy, expand_env, prepend_file, append_file, adguard_modifiers, max_entries, include_disabled, modified_after, only_type, expand_cidr, lowercase, normalize_trailing_dot, punycode, strip_www, dedup, sort, group_by_type, rule_limit_per_line, emit_metadata_comment, fail_on_empty, fail_on_warning, compress, comment_prefix, template, line_ending, output_format, scheme, include_subdomains, jobs, progress, split_output, count_only, check, dry_run, report_json, watch, verbose, quiet, help: _ } => {
+                let destination = Destination {
+                    output_file,
+                    output_dir,
+                    report_json,
+                    check,
+                    count_only,
+                    dry_run,
+                    split_output,
+                };
+                check_feature_flags("compile", &feature_flag);
+                let input_file = match input_glob {
+                    Some(pattern) => expand_input_glob(pattern, log_level(quiet, verbose))?,
+                    None => input_file,
+                };
+                check_compile_args(&targets, &input_file, &destination, watch);
+
+                let auto_header = if auto_header {
+                    AutoHeaderField::ALL.into_iter().filter(|x| !auto_header_exclude.contains(x)).collect()
+                } else {
+                    vec![]
+                };
+                let options = CompileOptions {
+                    input_format: format,
+                    trim_whitespace,
+                    target: targets[0],
+                    feature_flags: feature_flag,
+                    header_attributes,
+                    header_file,
+                    auto_header,
+                    header_dup_policy,
+                    expand_env,
+                    prepend_file,
+                    append_file,
+                    adguard_modifiers,
+                    max_entries,
+                    include_disabled,
+                    modified_after,
+                    only_types: only_type,
+                    expand_cidr,
+                    lowercase,
+                    normalize_trailing_dot,
+                    punycode,
+                    strip_www,
+                    dedup,
+                    sort,
+                    group_by_type,
+                    rule_limit_per_line,
+                    emit_metadata_comment,
+                    fail_on_empty,
+                    fail_on_warning,
+                    compression: compress,
+                    comment_prefix,
+                    template,
+                    line_ending,
+                    output_format,
+                    scheme,
+                    include_subdomains,
+                    jobs,
+                    progress,
+                    log_level: log_level(quiet, verbose),
+                };
+                if let Some(output_dir) = &destination.output_dir {
+                    std::fs::create_dir_all(output_dir).map_err(CompileError::from)?;
+                }
+
+                let result = compile_targets(&targets, &input_file, &options, &destination);
+                if !watch {
+                    return result
+                }
+
+                report_watch_result(result, options.log_level);
+                watch_inputs(&input_file, options.log_level, || report_watch_result(compile_targets(&targets, &input_file, &options, &destination), options.log_level))?;
             }
-            Args::Check { input_file } => {
-                syntax_check(input_file)?;
+            Args::Check { input_file, format, quiet: _, json: true } => {
+                check_json(&input_file, format)?;
             }
-        };
+            Args::Check { input_file, format, quiet, json: false } => {
+                syntax_check(&input_file, format, log_level(quiet, false))?;
+            }
+            Args::Decompile { target, input_file, output_file, quiet } => {
+                let log_level = log_level(quiet, false);
+                let unrecognized = decompile_file(&input_file, &output_file, target)?;
+                for x in &unrecognized {
+                    log_level.warn(format_args!("unrecognized line {}: {}", x.line_number, x.line));
+                }
+
+                if !unrecognized.is_empty() {
+                    log_level.warn(format_args!("{} lines were not recognized", unrecognized.len()));
+                }
+            }
+            Args::Diff { old_input, new_input, target, feature_flag, format } => {
+                check_feature_flags("diff", &feature_flag);
+                print!("{}", diff(&old_input, &new_input, target, feature_flag, format)?);
+            }
+            Args::Batch { manifest, verbose, quiet } => {
+                batch(&manifest, log_level(quiet, verbose))?;
+            }
+            Args::Schema => {
+                print_schema()?;
+            }
+        }
 
         Ok(())
     }
-}
 
-#[allow(clippy::too_many_lines)]
-fn compile(
-    input_file: PathBuf,
-    target: CompileTarget,
-    feature_flags: &[GenerateTargetPlatform],
-    output_file: PathBuf,
-    header_attributes: &[HeaderAttribute],
-    verbose: bool,
-) -> Result<(), CompileError> {
-    if feature_flags.is_empty() {
-        return Ok(())
+    /// Prints JSON Schema of the entry list. A closed stdout, such as by `schema | head`, is not an error.
+    fn print_schema() -> Result<(), ExecutionError> {
+        let mut stdout = std::io::stdout().lock();
+        match writeln!(stdout, "{}", entry_list_schema()).and_then(|()| stdout.flush()) {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+            result => Ok(result?),
+        }
     }
 
-    if target != CompileTarget::UBlockOrigin && feature_flags.contains(&GenerateTargetPlatform::GoogleSearchPrefix) {
-        return Err(CompileError::UnsupportedFeatureSet)
-    }
+    /// Prints every diagnostic of `input_file` as JSON, and fails if any of them is an error.
+    fn check_json(input_file: &Path, format: Option<InputFormat>) -> Result<(), ExecutionError> {
+        let diagnostics = syntax_check_report(input_file, format)?;
+        println!("{}", serde_json::to_string_pretty(&diagnostics).expect("Diagnostic is always serializable"));
 
-    let google_search_prefix = feature_flags.contains(&GenerateTargetPlatform::GoogleSearchPrefix);
-    let google_search_fuzzy = feature_flags.contains(&GenerateTargetPlatform::GoogleSearchFuzzy);
+        let errors = diagnostics.iter().filter(|x| x.severity == Severity::Error).count();
+        if errors > 0 {
+            return Err(ExecutionError::InvalidEntries { errors })
+        }
 
-    if google_search_prefix && google_search_fuzzy {
-        eprintln!("Both --include=GoogleSearchPrefix and --include=GoogleSearchFuzzy must not be used in same time.");
-        eprintln!("Please separate call.");
-        exit(1);
+        Ok(())
     }
 
-    let google = google_search_prefix || google_search_fuzzy;
+    /// Exits with the usage of `subcommand` if `--feature-flag` is given a pair which can not be used together, before
+    /// reading any file. The pairs come from the search engine table of the library, so that a new engine needs no change here.
+    fn check_feature_flags(subcommand: &str, feature_flags: &[GenerateTargetPlatform]) {
+        if let Some((first, second)) = conflicting_feature_flags(feature_flags) {
+            let mut command = Cli::command();
+            command.build();
+            let command = command.find_subcommand_mut(subcommand).expect("subcommand must exist");
+            command.error(ErrorKind::ArgumentConflict, format!("--feature-flag {first} can not be used with --feature-flag {second}")).exit();
+        }
+    }
+
+    /// Exits if the command line combines options which can not be used together.
+    fn check_compile_args(targets: &[CompileTarget], input_file: &[PathBuf], destination: &Destination, watch: bool) {
+        if targets.len() > 1 {
+            if destination.output_file.is_some() {
+                Cli::command().error(ErrorKind::ArgumentConflict, "--output can not be used with multiple --target; use --output-dir").exit();
+            }
+
+            if destination.report_json.is_some() {
+                Cli::command().error(ErrorKind::ArgumentConflict, "--report-json can not be used with multiple --target").exit();
+            }
+
+            if input_file.iter().any(|x| x.as_os_str() == "-") {
+                Cli::command().error(ErrorKind::ArgumentConflict, "stdin can not be read for multiple --target; specify --input").exit();
+            }
+        }
+
+        if destination.split_output.is_some() && destination.output_file.as_ref().is_some_and(|x| x.as_os_str() == "-") {
+            Cli::command().error(ErrorKind::ArgumentConflict, "--split-output can not write to stdout").exit();
+        }
+
+        if destination.dry_run {
+            if destination.output_file.as_ref().is_some_and(|x| x.as_os_str() == "-") {
+                Cli::command().error(ErrorKind::ArgumentConflict, "--dry-run has no file to compare with stdout").exit();
+            }
+
+            if destination.split_output.is_some() {
+                Cli::command().error(ErrorKind::ArgumentConflict, "--dry-run can not be used with --split-output").exit();
+            }
+        }
 
-    let list = syntax_check(input_file)?;
-    if verbose {
-        println!("loaded {} entries", list.0.len());
+        if watch && input_file.iter().any(|x| x.as_os_str() == "-") {
+            Cli::command().error(ErrorKind::ArgumentConflict, "stdin can not be watched; specify --input").exit();
+        }
     }
 
-    let mut writer = BufWriter::new(
-        File::options().write(true).truncate(true).create(true).open(output_file)?
-    );
+    /// Files matched by `pattern`, sorted by path.
+    fn expand_input_glob(pattern: String, log_level: LogLevel) -> Result<Vec<PathBuf>, ExecutionError> {
+        let expand = || {
+            let mut paths = glob::glob(&pattern)?.collect::<Result<Vec<_>, _>>()?;
+            if paths.is_empty() {
+                return Err(InputGlobError::NoMatch)
+            }
 
-    let comment = match target {
-        CompileTarget::UBlackList => "#",
-        CompileTarget::UBlockOrigin => "!",
-    };
+            paths.sort();
+            Ok(paths)
+        };
 
-    let mut outputs = vec![];
-    let header = header_attributes.iter().map(|x| {
-        let mut buf = String::with_capacity(determine_header_attribute_length(x));
-        buf.push_str(comment);
-        buf.push(' ');
-        buf.push_str(&x.key);
-        buf.push_str(": ");
-        buf.push_str(&x.value);
-        buf.push('\n');
+        match expand() {
+            Ok(paths) => {
+                log_level.verbose(format_args!("loading {} files matched by '{pattern}':", paths.len()));
+                for x in &paths {
+                    log_level.verbose(format_args!("  {}", x.display()));
+                }
 
-        buf
-    }).collect::<String>();
-    outputs.push(header);
-    if verbose {
-        println!("loaded {} headers", header_attributes.len());
+                Ok(paths)
+            }
+            Err(source) => Err(ExecutionError::InputGlob { pattern, source }),
+        }
     }
 
-    if feature_flags.contains(&GenerateTargetPlatform::Base) {
-        let entry_serialize: String = match target {
-            CompileTarget::UBlackList => {
-                /*
-                jq -r '.[] | select(.type == "domain") | .domain | ("*://" + . + "/*")' < "$data" >> "$dist"
-                jq -r '.[] | select(.type == "path") | .path | ("*://" + .)' < "$data" >> "$dist"
+    fn batch(manifest: &Path, log_level: LogLevel) -> Result<(), ExecutionError> {
+        let text = std::fs::read_to_string(manifest).map_err(ManifestError::from)?;
+        let Manifest { job: jobs } = if manifest.extension().is_some_and(|x| x == "toml") {
+            toml::from_str(&text).map_err(ManifestError::from)?
+        } else {
+            serde_json::from_str(&text).map_err(ManifestError::from)?
+        };
+        let base = manifest.parent().unwrap_or_else(|| Path::new(""));
+
+        let total = jobs.len();
+        let mut failures = vec![];
+        for job in jobs {
+            let input = job.input.iter().map(|x| base.join(x)).collect::<Vec<_>>();
+            let output = base.join(&job.output);
+            let options = CompileOptions {
+                header_attributes: job.header,
+                log_level,
+                ..CompileOptions::new(job.target, job.feature_flags)
+            };
+
+            match compile(&input, &output, &options) {
+                Ok(_) => log_level.info(format_args!("ok: {}", output.display())),
+                Err(e) => {
+                    log_level.error(format_args!("failed: {}: {e}", output.display()));
+                    failures.push(e);
+                }
+            }
+        }
 
-                */ */
+        if !failures.is_empty() {
+            return Err(ExecutionError::Batch { failures, total })
+        }
 
-                list.0.iter().map(|x| match x {
-                    Entry::Domain { match_method, domain } => {
-                        match *match_method {
-                            MatchMethod::Literal => format!("*://{domain}/*\n"),
-                        }
-                    }
-                    Entry::Path { match_method, path } => {
-                        match *match_method {
-                            MatchMethod::Literal => format!("*://{path}\n"),
-                        }
+        Ok(())
+    }
+
+    /// Where each target is written.
+    struct Destination {
+        output_file: Option<PathBuf>,
+        output_dir: Option<PathBuf>,
+        report_json: Option<PathBuf>,
+        check: bool,
+        count_only: bool,
+        dry_run: bool,
+        split_output: Option<NonZeroUsize>,
+    }
+
+    /// Compiles every target independently. A single target returns its error as is.
+    fn compile_targets(targets: &[CompileTarget], input_file: &[PathBuf], options: &CompileOptions, destination: &Destination) -> Result<(), ExecutionError> {
+        let mut failures = vec![];
+        let mut out_of_date = vec![];
+        for &target in targets {
+            let options = CompileOptions { target, ..options.clone() };
+            let output_file = || destination.output_dir.as_ref().map_or_else(
+                || destination.output_file.clone().expect("--output is required unless --output-dir, --count-only, or --check"),
+                |dir| dir.join(target.file_name(options.compression)),
+            );
+            let result = if destination.check {
+                compile_stats(input_file, &options)
+            } else if destination.count_only {
+                compile_stats(input_file, &options).inspect(|stats| {
+                    if targets.len() > 1 {
+                        println!("{target}:");
                     }
-                }).collect()
-            }
-            CompileTarget::UBlockOrigin => {
-                list.0.iter().map(|x| match x {
-                    Entry::Domain { match_method, domain: out }
-                    | Entry::Path { match_method, path: out } => {
-                        match *match_method {
-                            MatchMethod::Literal => format!("||{out}^\n"),
-                        }
+                    print!("{stats}");
+                })
+            } else if destination.dry_run {
+                let output_file = output_file();
+                dry_run(input_file, &output_file, &options).map(|(stats, changed)| {
+                    if changed {
+                        out_of_date.push(output_file);
                     }
-                }).collect()
+                    stats
+                })
+            } else {
+                let output_file = output_file();
+                destination.split_output.map_or_else(
+                    || compile(input_file, &output_file, &options),
+                    |lines_per_file| compile_split(input_file, &output_file, &options, lines_per_file).map(|(stats, _)| stats),
+                )
+            };
+            let result = result.and_then(|stats| destination.report_json.as_ref().map_or(Ok(()), |path| write_report(path, &options, &stats)));
+
+            match result {
+                Ok(()) => {}
+                Err(e) if targets.len() == 1 => return Err(e.into()),
+                Err(e) => failures.push((target, e)),
             }
-        };
+        }
 
-        if verbose {
-            println!("pushed General block rules");
+        if !failures.is_empty() {
+            return Err(ExecutionError::Targets { failures, total: targets.len() })
         }
 
-        outputs.push(entry_serialize);
+        if !out_of_date.is_empty() {
+            return Err(ExecutionError::OutOfDate { outputs: out_of_date })
+        }
+
+        Ok(())
     }
 
-    if google {
-        let href_operator = if google_search_prefix {
-            "^="
-        } else {
-            "*="
+    /// Compiles in memory, and prints the difference from the existing `output_file` to stderr.
+    /// A missing `output_file` is compared as empty. Returns whether they differ.
+    fn dry_run(input_file: &[PathBuf], output_file: &Path, options: &CompileOptions) -> Result<(CompileStats, bool), CompileError> {
+        let (output, stats) = compile_to_bytes(input_file, options)?;
+        let output = String::from_utf8_lossy(&output);
+        let existing = match std::fs::read(output_file) {
+            Ok(x) => String::from_utf8_lossy(&x).into_owned(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
         };
 
-        let cp = list.0.iter().filter_map(|x| {
-            match x {
-                Entry::Domain { match_method, domain } => {
-                    (*match_method == MatchMethod::Literal).then_some(domain)
-                }
-                Entry::Path { match_method, path } => {
-                    (*match_method == MatchMethod::Literal).then_some(path)
-                }
-            }
-        }).flat_map(|href_spec| {
-            [
-                format!(r#"www.google.*##.g:has(a[href{href_operator}"{href_spec}")"#),
-                format!(r#"www.google.*##.a[href{href_operator}"{href_spec}"]:upward(1)"#),
-            ]
-        }).collect::<Vec<_>>().join("\n");
-
-        if verbose {
-            println!("pushed Google block rules");
+        if existing == output {
+            options.log_level.verbose(format_args!("{} is up to date", output_file.display()));
+            return Ok((stats, false))
         }
-        outputs.push(cp);
+
+        let path = output_file.to_string_lossy();
+        eprint!("{}", TextDiff::from_lines(&existing, &output).unified_diff().header(&path, &path));
+
+        Ok((stats, true))
     }
 
-    if verbose {
-        println!("writing file");
+    fn report_watch_result(result: Result<(), ExecutionError>, log_level: LogLevel) {
+        match result {
+            Ok(()) => log_level.info("compiled"),
+            Err(e) => log_level.error(e),
+        }
     }
 
-    writer.write_all(outputs.join("").as_bytes())?;
+    /// Calls `f` whenever one of `inputs` is modified. Never returns unless watching fails.
+    fn watch_inputs(inputs: &[PathBuf], log_level: LogLevel, mut f: impl FnMut()) -> Result<(), ExecutionError> {
+        // editors may replace the file on save, so the parent directories are watched instead
+        let inputs = inputs.iter().map(|x| std::path::absolute(x).map_err(CompileError::from)).collect::<Result<Vec<_>, _>>()?;
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        for dir in inputs.iter().filter_map(|x| x.parent()) {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
 
-    Ok(())
-}
+        let is_relevant = |event: &notify::Result<Event>| event.as_ref().is_ok_and(|event| {
+            (event.kind.is_modify() || event.kind.is_create()) && event.paths.iter().any(|x| inputs.contains(x))
+        });
+        for event in &rx {
+            if let Err(e) = &event {
+                log_level.error(format_args!("watch error: {e}"));
+            }
 
-fn determine_header_attribute_length(attr: &HeaderAttribute) -> usize {
-    2 + attr.key.len() + 2 + attr.value.len() + 1
-}
+            if !is_relevant(&event) {
+                continue
+            }
+
+            // a save may emit several events
+            while rx.recv_timeout(Duration::from_millis(100)).is_ok() {}
+            f();
+        }
+
+        Ok(())
+    }
+
+    fn write_report(path: &Path, options: &CompileOptions, stats: &CompileStats) -> Result<(), CompileError> {
+        let report = CompileReport {
+            target: options.target,
+            feature_flags: &options.feature_flags,
+            stats,
+        };
+        let mut json = serde_json::to_string_pretty(&report).expect("CompileReport is always serializable");
+        json.push('\n');
+        std::fs::write(path, json)?;
 
-fn syntax_check(input: PathBuf) -> Result<EntryList, SyntaxCheckError> {
-    let mut json = String::new();
-    BufReader::new(File::open(input)?).read_to_string(&mut json)?;
-    let x = serde_json::from_str(&json)?;
-    Ok(x)
+        Ok(())
+    }
+
+    /// Compiles both inputs in memory, and returns the difference of their outputs in unified diff format.
+    fn diff(old_input: &Path, new_input: &Path, target: CompileTarget, feature_flags: Vec<GenerateTargetPlatform>, format: Option<InputFormat>) -> Result<String, CompileError> {
+        let options = CompileOptions {
+            input_format: format,
+            ..CompileOptions::new(target, feature_flags)
+        };
+        let compile_to_string = |input: &Path| -> Result<String, CompileError> {
+            let mut buf = vec![];
+            compile_to_writer(&[input.to_path_buf()], &mut buf, &options)?;
+            Ok(String::from_utf8_lossy(&buf).into_owned())
+        };
+        let old = compile_to_string(old_input)?;
+        let new = compile_to_string(new_input)?;
+
+        Ok(TextDiff::from_lines(&old, &new).unified_diff().header(&old_input.to_string_lossy(), &new_input.to_string_lossy()).to_string())
+    }
+
+    const fn log_level(quiet: bool, verbose: bool) -> LogLevel {
+        if quiet {
+            LogLevel::Quiet
+        } else if verbose {
+            LogLevel::Verbose
+        } else {
+            LogLevel::Normal
+        }
+    }
 }
@@ -0,0 +1,104 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn run(args: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_exclude_entry_compiler")).args(args).output().expect("binary must run").status.code()
+}
+
+/// Runs with `stdin` as the input, and returns the exit code, stdout, and stderr.
+fn run_with_stdin(args: &[&str], stdin: &str) -> (Option<i32>, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_exclude_entry_compiler")).args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("binary must run");
+    child.stdin.take().expect("stdin must be piped").write_all(stdin.as_bytes()).expect("stdin must be writable");
+    let output = child.wait_with_output().expect("binary must exit");
+    let text = |x| String::from_utf8(x).expect("output must be UTF-8");
+
+    (output.status.code(), text(output.stdout), text(output.stderr))
+}
+
+#[test]
+fn conflicting_feature_flags_are_usage_errors() {
+    // the input is never read
+    let input = "does-not-exist.json";
+
+    assert_eq!(run(&["compile", "-t", "uBlockOrigin", "-f", "GoogleSearchPrefix", "-f", "GoogleSearchFuzzy", "-i", input, "-o", "-"]), Some(2));
+    assert_eq!(run(&["compile", "-t", "uBlockOrigin", "-f", "BingFuzzy", "-f", "BingPrefix", "-i", input, "-o", "-"]), Some(2));
+    assert_eq!(run(&["diff", "-t", "uBlockOrigin", "-f", "YandexPrefix", "-f", "YandexFuzzy", input, input]), Some(2));
+}
+
+#[test]
+fn schema_to_closed_pipe_succeeds() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_exclude_entry_compiler")).arg("schema").stdout(Stdio::piped()).spawn().expect("binary must run");
+    // closed before the schema is written, as `schema | head` does
+    drop(child.stdout.take());
+
+    assert_eq!(child.wait().expect("binary must exit").code(), Some(0));
+}
+
+#[test]
+fn exit_code_tells_the_kind_of_error() {
+    let entries = r#"[{"type": "domain", "match": "literal", "domain": "a.com"}, {"type": "domain", "match": "substring", "domain": "ad"}]"#;
+    let compile = |extra: &[&str], stdin| {
+        let args = ["compile", "-t", "uBlockOrigin", "-f", "Base", "-o", "-"].iter().chain(extra).copied().collect::<Vec<_>>();
+
+        run_with_stdin(&args, stdin).0
+    };
+
+    assert_eq!(compile(&[], entries), Some(0));
+    assert_eq!(compile(&[], "["), Some(3));
+    assert_eq!(compile(&["-i", "does-not-exist.json"], entries), Some(4));
+    assert_eq!(run_with_stdin(&["compile", "-t", "Brave", "-f", "GoogleSearchPrefix", "-o", "-"], entries).0, Some(5));
+    assert_eq!(compile(&["--fail-on-empty"], "[]"), Some(6));
+    assert_eq!(compile(&["--max-entries", "1"], entries), Some(7));
+    // a substring shorter than 4 characters is warned
+    assert_eq!(compile(&["--fail-on-warning"], entries), Some(9));
+}
+
+#[test]
+fn check_json_reports_every_problem() {
+    let entries = r#"[
+        {"type": "domain", "match": "literal", "domain": ""},
+        {"type": "domain", "match": "substring", "domain": "ad"},
+        {"type": "domain", "match": "literal", "domain": "a.com"}
+    ]"#;
+
+    let (code, stdout, _) = run_with_stdin(&["check", "--json", "-"], entries);
+    let report = serde_json::from_str::<serde_json::Value>(&stdout).expect("report must be JSON");
+    let summary = report.as_array().expect("report must be an array").iter()
+        .map(|x| (x["severity"].as_str().expect("severity must be a string"), x["entry"].as_u64().expect("entry must be a number")))
+        .collect::<Vec<_>>();
+
+    assert_eq!(code, Some(3));
+    assert_eq!(summary, [("error", 1), ("warning", 2)]);
+
+    let (code, stdout, _) = run_with_stdin(&["check", "--json", "-"], "[");
+    let report = serde_json::from_str::<serde_json::Value>(&stdout).expect("report must be JSON");
+
+    assert_eq!(code, Some(3));
+    assert_eq!((report[0]["severity"].as_str(), report[0]["line"].as_u64()), (Some("error"), Some(1)));
+}
+
+#[test]
+fn syntax_error_position_is_reported_once() {
+    let (_, _, stderr) = run_with_stdin(&["check", "--color", "never", "-"], r#"[{"type": "domain" "match": "literal"}]"#);
+
+    assert!(stderr.contains("--> line 1, column 20"), "{stderr}");
+    // not repeated in the message as `at line 1 column 20`
+    assert_eq!(stderr.matches("column 20").count(), 1, "{stderr}");
+}
+
+#[test]
+fn skipped_ip_entries_are_warned_without_verbose() {
+    let entries = r#"[{"type": "domain", "match": "literal", "domain": "a.com"}, {"type": "ip", "match": "literal", "ip": "192.0.2.1"}]"#;
+
+    for target in ["hosts", "dnsmasq", "SmartDNS"] {
+        let (code, _, stderr) = run_with_stdin(&["compile", "-t", target, "-f", "Base", "-o", "-"], entries);
+
+        assert_eq!(code, Some(0));
+        assert!(stderr.contains("can not block IP address"), "{target}: {stderr}");
+    }
+}
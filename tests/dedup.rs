@@ -0,0 +1,31 @@
+use exclude_entry_compiler::{compile_entries, CompileOptions, CompileTarget, DedupMode, Entry, EntryAttributes, GenerateTargetPlatform, MatchMethod};
+
+fn domain(domain: &str, note: Option<&str>) -> Entry {
+    Entry::Domain {
+        match_method: MatchMethod::Literal,
+        domain: domain.to_string(),
+        attributes: EntryAttributes {
+            note: note.map(str::to_string),
+            ..EntryAttributes::default()
+        },
+    }
+}
+
+fn compile(entries: Vec<Entry>, dedup: DedupMode) -> String {
+    let options = CompileOptions {
+        dedup: Some(dedup),
+        ..CompileOptions::new(CompileTarget::UBlackList, vec![GenerateTargetPlatform::Base])
+    };
+    let mut buf = vec![];
+    compile_entries(entries, &mut buf, &options).expect("compile must succeed");
+
+    String::from_utf8(buf).expect("output must be UTF-8")
+}
+
+#[test]
+fn differing_notes_do_not_keep_duplicates() {
+    let entries = || vec![domain("example.com", Some("first")), domain("example.com", Some("second")), domain("example.com", None)];
+
+    assert_eq!(compile(entries(), DedupMode::Exact), "# first\n*://example.com/*\n");
+    assert_eq!(compile(entries(), DedupMode::Exact), compile(entries(), DedupMode::Semantic));
+}
@@ -0,0 +1,66 @@
+use std::fmt::Write as _;
+use exclude_entry_compiler::{compile_entries, CompileOptions, CompileTarget, DedupMode, Entry, EntryList, GenerateTargetPlatform};
+
+/// Enough entries to span several chunks of `--jobs`, including duplicates and covered entries.
+fn entries() -> Vec<Entry> {
+    let mut entries = String::from("[\n");
+    for i in 0..10_000 {
+        let _ = writeln!(entries, r#"{{"type": "domain", "match": "literal", "domain": "sub{}.example{}.com"}},"#, i % 7, i % 1000);
+        let _ = writeln!(entries, r#"{{"type": "path", "match": "literal", "path": "example{i}.org/bad"}},"#);
+        if i % 100 == 0 {
+            let _ = writeln!(entries, r#"{{"type": "domain", "match": "wildcard", "domain": "*.example{i}.com"}},"#);
+            let _ = writeln!(entries, r#"{{"type": "comment", "text": "group {i}"}},"#);
+        }
+    }
+    entries.push_str(r#"{"type": "ip", "match": "literal", "ip": "192.0.2.1"}"#);
+    entries.push_str("\n]");
+
+    serde_json::from_str::<EntryList>(&entries).expect("fixture must be an entry list").0
+}
+
+fn compile(entries: &[Entry], options: &CompileOptions) -> Vec<u8> {
+    let mut buf = vec![];
+    compile_entries(entries.to_vec(), &mut buf, options).expect("compile must succeed");
+
+    buf
+}
+
+fn options(jobs: usize) -> CompileOptions {
+    CompileOptions {
+        dedup: Some(DedupMode::Semantic),
+        sort: true,
+        jobs,
+        ..CompileOptions::new(CompileTarget::UBlockOrigin, vec![GenerateTargetPlatform::Base])
+    }
+}
+
+#[test]
+fn compiling_twice_emits_identical_bytes() {
+    let entries = entries();
+    let options = options(1);
+
+    let first = compile(&entries, &options);
+    assert!(!first.is_empty());
+    assert_eq!(first, compile(&entries, &options));
+}
+
+#[test]
+fn jobs_do_not_change_output() {
+    let entries = entries();
+
+    let sequential = compile(&entries, &options(1));
+    for _ in 0..3 {
+        assert_eq!(sequential, compile(&entries, &options(4)));
+    }
+}
+
+#[test]
+fn grouping_emits_identical_bytes() {
+    let entries = entries();
+    let options = CompileOptions {
+        group_by_type: true,
+        ..options(4)
+    };
+
+    assert_eq!(compile(&entries, &options), compile(&entries, &options));
+}
@@ -0,0 +1,43 @@
+use exclude_entry_compiler::{compile_to_string, CompileTarget, EntryList, GenerateTargetPlatform};
+
+const ENTRIES: &str = r#"[
+{"type": "domain", "match": "literal", "domain": "example.com"},
+{"type": "path", "match": "literal", "path": "example.org/bad"}
+]"#;
+
+fn compile(feature_flags: &[GenerateTargetPlatform]) -> String {
+    let list = serde_json::from_str::<EntryList>(ENTRIES).expect("fixture must be an entry list");
+
+    compile_to_string(&list, CompileTarget::UBlockOrigin, feature_flags, &[]).expect("compile must succeed")
+}
+
+#[test]
+fn google_only_emits_no_base_rules() {
+    let output = compile(&[GenerateTargetPlatform::GoogleSearchFuzzy]);
+
+    assert!(!output.is_empty());
+    assert!(output.lines().all(|x| x.starts_with("www.google.*##")), "{output}");
+    assert!(!output.lines().any(|x| x.starts_with("||") && x.ends_with('^')), "{output}");
+}
+
+#[test]
+fn base_only_emits_no_google_rules() {
+    let output = compile(&[GenerateTargetPlatform::Base]);
+
+    assert_eq!(output, "||example.com^\n||example.org/bad^\n");
+}
+
+#[test]
+fn no_feature_flag_emits_nothing() {
+    let output = compile(&[]);
+
+    assert_eq!(output, "");
+}
+
+#[test]
+fn google_rules_do_not_end_with_line_break() {
+    let output = compile(&[GenerateTargetPlatform::Base, GenerateTargetPlatform::GoogleSearchPrefix]);
+
+    assert!(output.starts_with("||example.com^\n||example.org/bad^\nwww.google.*##"), "{output}");
+    assert!(output.ends_with(r#"www.google.*##.a[href^="example.org/bad"]:upward(1)"#), "{output}");
+}
@@ -0,0 +1,94 @@
+use proptest::prelude::*;
+use std::num::NonZeroUsize;
+use exclude_entry_compiler::{compile_entries, compile_to_string, decompile, CompileOptions, CompileTarget, Entry, EntryAttributes, EntryList, GenerateTargetPlatform, MatchMethod};
+
+fn domain() -> impl Strategy<Value = String> {
+    let label = "[a-z0-9]([a-z0-9-]{0,10}[a-z0-9])?";
+    (prop::collection::vec(label, 1..4), "[a-z]{2,6}").prop_map(|(labels, tld)| format!("{}.{tld}", labels.join(".")))
+}
+
+fn path() -> impl Strategy<Value = String> {
+    (domain(), prop::collection::vec("[A-Za-z0-9._~-]{1,8}", 1..4)).prop_map(|(domain, segments)| format!("{domain}/{}", segments.join("/")))
+}
+
+fn entry() -> impl Strategy<Value = Entry> {
+    prop_oneof![
+        (domain(), any::<bool>()).prop_map(|(domain, exception)| Entry::Domain {
+            match_method: MatchMethod::Literal,
+            domain,
+            attributes: EntryAttributes {
+                exception,
+                ..EntryAttributes::default()
+            },
+        }),
+        (path(), any::<bool>()).prop_map(|(path, exception)| Entry::Path {
+            match_method: MatchMethod::Literal,
+            path,
+            attributes: EntryAttributes {
+                exception,
+                ..EntryAttributes::default()
+            },
+        }),
+    ]
+}
+
+fn compile(list: &EntryList) -> String {
+    compile_to_string(list, CompileTarget::UBlockOrigin, &[GenerateTargetPlatform::Base], &[]).expect("generated entries must be valid")
+}
+
+proptest! {
+    #[test]
+    fn compile_decompile_compile_is_stable(entries in prop::collection::vec(entry(), 0..16)) {
+        let list = EntryList(entries);
+        let compiled = compile(&list);
+
+        let decompiled = decompile(&compiled, CompileTarget::UBlockOrigin);
+        prop_assert!(decompiled.unrecognized.is_empty(), "{:?}", decompiled.unrecognized);
+        prop_assert_eq!(&decompiled.entries.0, &list.0);
+        prop_assert_eq!(compile(&decompiled.entries), compiled);
+    }
+}
+
+#[test]
+fn ublock_origin_accepts_url_patterns() {
+    let decompiled = decompile("||a.com^\n*://c.com/*\n", CompileTarget::UBlockOrigin);
+
+    assert!(decompiled.unrecognized.is_empty(), "{:?}", decompiled.unrecognized);
+    assert_eq!(decompiled.entries.0.iter().map(Entry::value).collect::<Vec<_>>(), ["a.com", "c.com"]);
+}
+
+#[test]
+fn notes_and_joined_lines_are_decompiled() {
+    let list = serde_json::from_str::<EntryList>(r#"[
+        {"type": "domain", "match": "literal", "domain": "a.com"},
+        {"type": "domain", "match": "literal", "domain": "b.com", "note": "bee"},
+        {"type": "domain", "match": "literal", "domain": "c.com"},
+        {"type": "domain", "match": "literal", "domain": "d.com"}
+    ]"#).expect("fixture must be an entry list");
+    let compile = |list: &EntryList, target| {
+        let options = CompileOptions {
+            rule_limit_per_line: NonZeroUsize::new(2),
+            ..CompileOptions::new(target, vec![GenerateTargetPlatform::Base])
+        };
+        let mut buf = vec![];
+        compile_entries(list.0.clone(), &mut buf, &options).expect("entries must compile");
+
+        String::from_utf8(buf).expect("output must be UTF-8")
+    };
+
+    // `||b.com^ # bee`, `0.0.0.0 a.com c.com`, and `address=/a.com/c.com/0.0.0.0`
+    for target in [CompileTarget::UBlockOrigin, CompileTarget::Hosts, CompileTarget::Dnsmasq] {
+        let compiled = compile(&list, target);
+
+        let decompiled = decompile(&compiled, target);
+        assert!(decompiled.unrecognized.is_empty(), "{target}: {:?}", decompiled.unrecognized);
+        assert_eq!(decompiled.entries.0.iter().map(Entry::value).collect::<Vec<_>>(), ["a.com", "b.com", "c.com", "d.com"], "{target}: {compiled}");
+        // dnsmasq writes the note as a comment line, which is skipped
+        if target.inline_comment_prefix().is_some() {
+            assert_eq!(compile(&decompiled.entries, target), compiled, "{target}");
+        }
+    }
+
+    let decompiled = decompile("0.0.0.0 b.com # bee\n", CompileTarget::Hosts);
+    assert_eq!(decompiled.entries.0.iter().map(|x| (x.value(), x.note())).collect::<Vec<_>>(), [("b.com", Some("bee"))]);
+}
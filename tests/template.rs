@@ -0,0 +1,27 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use exclude_entry_compiler::{compile_to_bytes, CompileOptions, CompileTarget, GenerateTargetPlatform};
+
+const ENTRIES: &str = r#"[
+{"type": "domain", "match": "literal", "domain": "example.com", "modifiers": ["third-party"], "scope": ["a.com", "b.com"]}
+]"#;
+
+/// Written to a file, since only compiling files returns [`exclude_entry_compiler::CompileStats`].
+fn input(name: &str) -> PathBuf {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.json"));
+    fs::write(&path, ENTRIES).expect("input must be writable");
+
+    path
+}
+
+#[test]
+fn template_ignores_modifiers_and_scope() {
+    let options = CompileOptions {
+        template: Some("*B.{value}$all".to_string()),
+        ..CompileOptions::new(CompileTarget::UBlockOrigin, vec![GenerateTargetPlatform::Base])
+    };
+    let (output, stats) = compile_to_bytes(&[input("template_modifiers")], &options).expect("compile must succeed");
+
+    assert_eq!(String::from_utf8(output).expect("output must be UTF-8"), "*B.example.com$all\n");
+    assert_eq!(stats.warnings.len(), 2, "{:?}", stats.warnings);
+}